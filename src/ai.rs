@@ -0,0 +1,341 @@
+use rand::Rng;
+
+use field::*;
+use fleet::*;
+use rng::*;
+use player::*;
+
+#[derive(Copy, Clone)]
+pub enum Difficulty {
+    EASY = 0,
+    HARD = 1,
+}
+
+#[derive(Clone)]
+pub struct AiState {
+    pub difficulty: Difficulty,
+    pub hits: Vec<Coord>,
+    pub blocked: Vec<Vec<bool>>,
+    // Shapes of the ships not sunk yet
+    pub remaining: Vec<Vec<Coord>>
+}
+
+// Tries a few times from scratch, returns false if the fleet doesn't fit the field
+pub fn ai_place_ship(field : &mut Field, fleet: &Fleet, rng: &mut GameRng) -> bool {
+    for _ in 0..100 {
+        let mut attempt = field.clone();
+        if ai_try_place_ship(&mut attempt, fleet, rng) {
+            *field = attempt;
+            return true;
+        }
+    }
+    return false;
+}
+
+fn ai_try_place_ship(field : &mut Field, fleet: &Fleet, rng: &mut GameRng) -> bool {
+    for kind in fleet_ships(fleet) {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            if attempts > 1000 {
+                return false;
+            }
+            let x = rng.gen_range(0, field.width as u32);
+            let y = rng.gen_range(0, field.height as u32);
+            let mut curShip = shaped_ship(&kind.shape, Coord {y : y, x : x});
+            if is_straight(&kind.shape) {
+                if rng.gen_range(0, 2) == 1 {
+                    rotate_ship(&mut curShip);
+                }
+            }
+            else {
+                // Any of the four turns of the shape or of its mirror
+                if rng.gen_range(0, 2) == 1 {
+                    mirror_ship(&mut curShip);
+                }
+                for _ in 0..rng.gen_range(0, 4) {
+                    rotate_ship(&mut curShip);
+                }
+            }
+            if collision(field, &mut curShip) {
+                continue;
+            }
+            else if curShip.can_be_placed {
+                place_ship(field, &curShip, &kind.name);
+                break;
+            }
+        }
+    }
+    return true;
+}
+
+pub fn new_ai_state(difficulty: Difficulty, width: usize, height: usize, fleet: &Fleet) -> AiState {
+    AiState {
+        difficulty : difficulty,
+        hits : vec![],
+        blocked : vec![vec![false; width]; height],
+        remaining : fleet_shapes(fleet)
+    }
+}
+
+fn is_shootable(field: &OpponentView, ai: &AiState, y: i32, x: i32) -> bool {
+    if !field.contains(y, x) {
+        return false;
+    }
+    if ai.blocked[y as usize][x as usize] {
+        return false;
+    }
+    field.knowledge(Pos { y : y, x : x }) == Some(Knowledge::UNKNOWN)
+}
+
+// Marks cells where no ship can be according to the adjacency rule
+fn ai_block_around(field: &OpponentView, ai: &mut AiState, y: i32, x: i32, diagonal_only: bool) {
+    let mut around = field.forbidden_around();
+    if !diagonal_only {
+        around.push((0, 0));
+    }
+    for &(dy, dx) in around.iter() {
+        if diagonal_only && (dy == 0 || dx == 0) {
+            continue;
+        }
+        let ny = y + dy;
+        let nx = x + dx;
+        if field.contains(ny, nx) {
+            ai.blocked[ny as usize][nx as usize] = true;
+        }
+    }
+}
+
+fn ai_choose_target(field: &OpponentView, ai: &AiState, rng: &mut GameRng) -> Option<Coord> {
+    let mut targets : Vec<Coord> = vec![];
+
+    // Target mode: probe around hits of the ship we are finishing off
+    if ai.hits.len() != 0 {
+        // Only straight ships go on in the line of their first two hits
+        let lines = ai.remaining.iter().all(|shape| is_straight(shape));
+        let horizontal = lines && ai.hits.len() >= 2 && ai.hits[0].y == ai.hits[1].y;
+        let vertical = lines && ai.hits.len() >= 2 && ai.hits[0].x == ai.hits[1].x;
+        ai_probe_around_hits(field, ai, horizontal, vertical, &mut targets);
+        // If ships may touch, the hits can belong to different ships
+        if targets.len() == 0 {
+            ai_probe_around_hits(field, ai, false, false, &mut targets);
+        }
+    }
+
+    // Hunt mode: any cell that still can hold a ship
+    if targets.len() == 0 {
+        for i in 0..field.height as i32 {
+            for j in 0..field.width as i32 {
+                if is_shootable(field, ai, i, j) {
+                    targets.push(Coord { y : i as u32, x : j as u32 });
+                }
+            }
+        }
+    }
+
+    if targets.len() == 0 {
+        return None;
+    }
+    return Some(targets[rng.gen_range(0, targets.len())]);
+}
+
+fn ai_probe_around_hits(field: &OpponentView, ai: &AiState, horizontal: bool, vertical: bool, targets: &mut Vec<Coord>) {
+    for hit in ai.hits.iter() {
+        let y = hit.y as i32;
+        let x = hit.x as i32;
+        let mut around : Vec<(i32, i32)> = vec![];
+        if !vertical {
+            around.push((y, x-1));
+            around.push((y, x+1));
+        }
+        if !horizontal {
+            around.push((y-1, x));
+            around.push((y+1, x));
+        }
+        for &(ny, nx) in around.iter() {
+            if is_shootable(field, ai, ny, nx) {
+                targets.push(Coord { y : ny as u32, x : nx as u32 });
+            }
+        }
+    }
+}
+
+// Whether a ship with these cells, offsets from y and x, can be there
+fn ai_placement_fits(field: &OpponentView, ai: &AiState, y: i32, x: i32, cells: &Vec<Coord>) -> Option<usize> {
+    let mut covered_hits = 0;
+    for cell in cells.iter() {
+        let cy = y + cell.y as i32;
+        let cx = x + cell.x as i32;
+        if !field.contains(cy, cx) || ai.blocked[cy as usize][cx as usize] {
+            return None;
+        }
+        match field.knowledge(Pos { y : cy, x : cx }) {
+            Some(Knowledge::MISS) | None => { return None; }
+            Some(Knowledge::HIT) | Some(Knowledge::SUNK) => { covered_hits += 1; }
+            Some(Knowledge::UNKNOWN) => {}
+        }
+    }
+
+    // Every hit next to the placement that can't touch it has to be a part of it
+    let around = field.forbidden_around();
+    for hit in ai.hits.iter() {
+        let hy = hit.y as i32;
+        let hx = hit.x as i32;
+        let inside = cells.iter().any(|cell| y + cell.y as i32 == hy && x + cell.x as i32 == hx);
+        if inside {
+            continue;
+        }
+        for cell in cells.iter() {
+            let cy = y + cell.y as i32;
+            let cx = x + cell.x as i32;
+            for &(dy, dx) in around.iter() {
+                if cy + dy == hy && cx + dx == hx {
+                    return None;
+                }
+            }
+        }
+    }
+    return Some(covered_hits);
+}
+
+fn ai_choose_density_target(field: &OpponentView, ai: &AiState, rng: &mut GameRng) -> Option<Coord> {
+    let mut density = vec![vec![0u32; field.width]; field.height];
+    for shape in ai.remaining.iter() {
+        for (_, cells) in shape_orientations(shape) {
+            for y in 0..field.height as i32 {
+                for x in 0..field.width as i32 {
+                    let covered_hits = match ai_placement_fits(field, ai, y, x, &cells) {
+                        Some(covered_hits) => covered_hits,
+                        None => { continue; }
+                    };
+                    // While finishing off a ship only placements through its hits matter
+                    if ai.hits.len() != 0 && covered_hits == 0 {
+                        continue;
+                    }
+                    let weight = if covered_hits > 0 { covered_hits as u32 * 10 } else { 1 };
+                    for cell in cells.iter() {
+                        density[(y + cell.y as i32) as usize][(x + cell.x as i32) as usize] += weight;
+                    }
+                }
+            }
+        }
+    }
+
+    let mut best = 0;
+    let mut targets : Vec<Coord> = vec![];
+    for i in 0..field.height as i32 {
+        for j in 0..field.width as i32 {
+            if !is_shootable(field, ai, i, j) || density[i as usize][j as usize] == 0 {
+                continue;
+            }
+            let value = density[i as usize][j as usize];
+            if value > best {
+                best = value;
+                targets.clear();
+            }
+            if value == best {
+                targets.push(Coord { y : i as u32, x : j as u32 });
+            }
+        }
+    }
+
+    if targets.len() == 0 {
+        return None;
+    }
+    return Some(targets[rng.gen_range(0, targets.len())]);
+}
+
+pub fn ai_choose(field: &OpponentView, ai: &AiState, rng: &mut GameRng) -> Option<Coord> {
+    let density_target = match ai.difficulty {
+        Difficulty::HARD => ai_choose_density_target(field, ai, rng),
+        Difficulty::EASY => None
+    };
+    match density_target {
+        Some(target) => Some(target),
+        None => ai_choose_target(field, ai, rng)
+    }
+}
+
+pub fn ai_observe(field: &OpponentView, ai: &mut AiState, target: Coord, result: &ShotResult) {
+    match *result {
+        ShotResult::HIT => {
+            ai.hits.push(target);
+            // Depending on the rule, no other ship can be diagonally to a hit,
+            // and a straight one doesn't go on there either
+            if ai.remaining.iter().all(|shape| is_straight(shape)) {
+                ai_block_around(field, ai, target.y as i32, target.x as i32, true);
+            }
+        }
+        ShotResult::SUNK(ref cells) => {
+            ai.hits.retain(|hit| !cells.contains(hit));
+            for cell in cells.iter() {
+                ai_block_around(field, ai, cell.y as i32, cell.x as i32, false);
+            }
+            let sunk = ai.remaining.iter().position(|shape| find_orientation(shape, cells).is_some())
+                .or_else(|| ai.remaining.iter().position(|shape| shape.len() == cells.len()));
+            match sunk {
+                Some(index) => { ai.remaining.remove(index); }
+                None => {}
+            }
+        }
+        _ => {}
+    }
+}
+
+// All targets are chosen before any of them is resolved. Every chosen cell is
+// treated as known while choosing the next one, so the shots spread over all
+// neighbours of a hit first and then over the most likely cells.
+pub fn ai_choose_salvo(field: &OpponentView, ai: &AiState, shots: usize, rng: &mut GameRng) -> Vec<Coord> {
+    let mut planning = ai.clone();
+    let mut targets : Vec<Coord> = vec![];
+    while targets.len() < shots {
+        match ai_choose(field, &planning, rng) {
+            Some(target) => {
+                planning.blocked[target.y as usize][target.x as usize] = true;
+                targets.push(target);
+            }
+            None => { break; }
+        }
+    }
+    return targets;
+}
+
+// Built-in AI as one side of a game, see `player`
+pub struct AiPlayer {
+    pub state: AiState
+}
+
+pub fn ai_player(difficulty: Difficulty, width: usize, height: usize, fleet: &Fleet) -> AiPlayer {
+    AiPlayer { state : new_ai_state(difficulty, width, height, fleet) }
+}
+
+impl Player for AiPlayer {
+    fn name(&self) -> String {
+        match self.state.difficulty {
+            Difficulty::EASY => "AI (easy)".to_string(),
+            Difficulty::HARD => "AI (hard)".to_string()
+        }
+    }
+
+    fn place_fleet(&mut self, own: &mut Field, fleet: &Fleet, rng: &mut GameRng) -> Result<(), Failure> {
+        if ai_place_ship(own, fleet, rng) { Ok(()) } else { Err(Failure::ERROR("The fleet doesn't fit this field".to_string())) }
+    }
+
+    fn choose_shots(&mut self, _own: &Field, target: &OpponentView, shots: usize, rng: &mut GameRng) -> Result<Vec<Coord>, Failure> {
+        if shots == 1 {
+            Ok(ai_choose(target, &self.state, rng).into_iter().collect())
+        }
+        else {
+            Ok(ai_choose_salvo(target, &self.state, shots, rng))
+        }
+    }
+
+    fn observe(&mut self, _own: &Field, target: &OpponentView, coord: Coord, result: &ShotResult) -> Result<(), Failure> {
+        ai_observe(target, &mut self.state, coord, result);
+        Ok(())
+    }
+
+    fn ai_state(&self) -> Option<&AiState> {
+        Some(&self.state)
+    }
+}
@@ -0,0 +1,96 @@
+// External program playing through its stdin and stdout, one line per message.
+// What the lines mean is up to `headless`.
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub struct Bot {
+    pub command: String,
+    // How long an answer may take
+    pub timeout_ms: u64,
+    child: Child,
+    // None once closed
+    stdin: Option<ChildStdin>,
+    lines: Receiver<String>
+}
+
+// Command is split on whitespace, the first word is the program
+pub fn spawn_bot(command: &str, timeout_ms: u64) -> Result<Bot, String> {
+    let words : Vec<&str> = command.split_whitespace().collect();
+    if words.len() == 0 {
+        return Err("Empty bot command".to_string());
+    }
+    let mut child = try!(Command::new(words[0])
+                         .args(&words[1..])
+                         .stdin(Stdio::piped())
+                         .stdout(Stdio::piped())
+                         .spawn()
+                         .map_err(|e| format!("Can't start '{}': {}", command, e)));
+    let stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+
+    // Lines are read on their own thread, so waiting for them can time out
+    let (sender, lines) = channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            match line {
+                Ok(line) => {
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => { break; }
+            }
+        }
+    });
+
+    Ok(Bot {
+        command : command.to_string(),
+        timeout_ms : timeout_ms,
+        child : child,
+        stdin : Some(stdin),
+        lines : lines
+    })
+}
+
+impl Bot {
+    pub fn send(&mut self, line: &str) -> Result<(), String> {
+        let stdin = match self.stdin {
+            Some(ref mut stdin) => stdin,
+            None => { return Err("bot's stdin is closed".to_string()); }
+        };
+        stdin.write_all(format!("{}\n", line).as_bytes())
+            .and_then(|_| stdin.flush())
+            .map_err(|_| "bot has exited".to_string())
+    }
+
+    // The bot reads end of file after the lines sent so far
+    pub fn close(&mut self) {
+        self.stdin = None;
+    }
+
+    // Empty lines are skipped, but the answer still has to come in time
+    pub fn receive(&mut self) -> Result<String, String> {
+        let deadline = Instant::now() + Duration::from_millis(self.timeout_ms);
+        loop {
+            let now = Instant::now();
+            let received = if now < deadline { self.lines.recv_timeout(deadline - now) } else { Err(RecvTimeoutError::Timeout) };
+            match received {
+                Ok(ref line) if line.trim().len() == 0 => {}
+                Ok(line) => { return Ok(line.trim().to_string()); }
+                Err(RecvTimeoutError::Timeout) => { return Err(format!("no answer in {} ms", self.timeout_ms)); }
+                Err(RecvTimeoutError::Disconnected) => { return Err("bot has exited".to_string()); }
+            }
+        }
+    }
+}
+
+impl Drop for Bot {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
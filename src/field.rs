@@ -0,0 +1,666 @@
+#[derive(Copy, Clone)]
+pub enum CellType {
+    EMPTY = 0,
+    SHOT = 1,
+    SHIP = 2,
+    COLLISION_SHIP = 3,
+}
+
+// Which contacts between different ships are allowed
+#[derive(Copy, Clone)]
+pub enum Adjacency {
+    NO_TOUCHING = 0,
+    CORNERS_ALLOWED = 1,
+    TOUCHING_ALLOWED = 2,
+}
+
+#[derive(Clone)]
+pub enum ShotResult {
+    MISS,
+    HIT,
+    SUNK(Vec<Coord>),
+    ALREADY_SHOT,
+    OUT_OF_BOUNDS,
+}
+
+// What a side knows about a cell of the other side's field
+#[derive(Copy, Clone, PartialEq)]
+pub enum Knowledge {
+    UNKNOWN,
+    MISS,
+    HIT,
+    SUNK,
+}
+
+#[derive(Copy, Clone)]
+pub struct Cell {
+    pub Type: CellType,
+    pub ship: Option<usize>
+}
+
+// Cell of a field
+#[derive(Copy, Clone, PartialEq)]
+pub struct Coord {
+    pub y: u32,
+    pub x: u32
+}
+
+// Position that may be off the field, like a ship moved past the border or
+// the neighbour of a border cell. Fields turn it into a `Coord` if it's on them
+#[derive(Copy, Clone, PartialEq)]
+pub struct Pos {
+    pub y: i32,
+    pub x: i32
+}
+
+impl Coord {
+    pub fn pos(self) -> Pos {
+        Pos { y : self.y as i32, x : self.x as i32 }
+    }
+}
+
+impl Pos {
+    pub fn offset(self, dy: i32, dx: i32) -> Pos {
+        Pos { y : self.y + dy, x : self.x + dx }
+    }
+}
+
+// How a ship lies compared to the shape of its type: mirrored left to right
+// first if at all, then turned clockwise by quarter turns
+#[derive(Copy, Clone, PartialEq)]
+pub struct Orientation {
+    pub turns: u32,
+    pub mirrored: bool
+}
+
+pub static UPRIGHT: Orientation = Orientation { turns : 0, mirrored : false };
+
+// Ship placed on a field for good. Its id is its index in `Field::ships`
+// and is also kept in its cells
+#[derive(Clone)]
+pub struct PlacedShip {
+    pub id: usize,
+    // Empty when the ship type isn't known, like for ships sunk by a remote player
+    pub name: String,
+    // Upright as long as the ship type isn't known
+    pub orientation: Orientation,
+    pub cells: Vec<Coord>,
+    pub hits: usize
+}
+
+impl PlacedShip {
+    pub fn is_sunk(&self) -> bool {
+        self.hits >= self.cells.len()
+    }
+}
+
+pub struct ShipCell {
+    pub coord: Pos,
+    pub cell: CellType
+}
+
+// Ship being placed. Its cells are offsets from `coord`, the top left corner
+// of the ship, which can be anywhere while it's moved around
+pub struct Ship {
+    pub coord: Pos,
+    pub cells: Vec<ShipCell>,
+    pub orientation: Orientation,
+    pub can_be_placed: bool
+}
+
+// Board size limits supported by the game, any size works for the engine itself
+pub static MIN_FIELD_SIZE: usize = 6;
+pub static MAX_FIELD_SIZE: usize = 26;
+
+#[derive(Clone)]
+pub struct Field {
+    pub width: usize,
+    pub height: usize,
+    pub adjacency: Adjacency,
+    cells: Vec<Vec<Cell>>,
+    // Ships placed for good, in the order of their ids
+    pub ships: Vec<PlacedShip>
+}
+
+// The other side's field as seen by its opponent. Ships are not stored in it
+// at all, so whoever only gets this can't find out where they are
+#[derive(Clone)]
+pub struct OpponentView {
+    pub width: usize,
+    pub height: usize,
+    pub adjacency: Adjacency,
+    cells: Vec<Vec<Knowledge>>
+}
+
+// Offsets of the cells around a ship cell that can't belong to another ship
+pub fn forbidden_around(adjacency: Adjacency) -> Vec<(i32, i32)> {
+    let mut around : Vec<(i32, i32)> = vec![];
+    for dy in -1..2 {
+        for dx in -1..2 {
+            let allowed = match adjacency {
+                Adjacency::NO_TOUCHING => true,
+                Adjacency::CORNERS_ALLOWED => dy == 0 || dx == 0,
+                Adjacency::TOUCHING_ALLOWED => false
+            };
+            if allowed && (dy != 0 || dx != 0) {
+                around.push((dy, dx));
+            }
+        }
+    }
+    return around;
+}
+
+impl Field {
+    pub fn contains(&self, y: i32, x: i32) -> bool {
+        y >= 0 && x >= 0 && (y as usize) < self.height && (x as usize) < self.width
+    }
+
+    pub fn coord(&self, pos: Pos) -> Option<Coord> {
+        if self.contains(pos.y, pos.x) { Some(Coord { y : pos.y as u32, x : pos.x as u32 }) } else { None }
+    }
+
+    // None off the field
+    pub fn cell(&self, pos: Pos) -> Option<&Cell> {
+        match self.coord(pos) {
+            Some(coord) => Some(&self.cells[coord.y as usize][coord.x as usize]),
+            None => None
+        }
+    }
+
+    pub fn cell_mut(&mut self, pos: Pos) -> Option<&mut Cell> {
+        match self.coord(pos) {
+            Some(coord) => Some(&mut self.cells[coord.y as usize][coord.x as usize]),
+            None => None
+        }
+    }
+
+    pub fn forbidden_around(&self) -> Vec<(i32, i32)> {
+        forbidden_around(self.adjacency)
+    }
+}
+
+impl OpponentView {
+    pub fn contains(&self, y: i32, x: i32) -> bool {
+        y >= 0 && x >= 0 && (y as usize) < self.height && (x as usize) < self.width
+    }
+
+    pub fn coord(&self, pos: Pos) -> Option<Coord> {
+        if self.contains(pos.y, pos.x) { Some(Coord { y : pos.y as u32, x : pos.x as u32 }) } else { None }
+    }
+
+    // None off the field
+    pub fn knowledge(&self, pos: Pos) -> Option<Knowledge> {
+        match self.coord(pos) {
+            Some(coord) => Some(self.cells[coord.y as usize][coord.x as usize]),
+            None => None
+        }
+    }
+
+    pub fn forbidden_around(&self) -> Vec<(i32, i32)> {
+        forbidden_around(self.adjacency)
+    }
+}
+
+pub fn empty_field(width: usize, height: usize, adjacency: Adjacency) -> Field {
+    Field {
+        width : width,
+        height : height,
+        adjacency : adjacency,
+        cells : vec![vec![Cell { Type : CellType::EMPTY, ship : None }; width]; height],
+        ships : vec![]
+    }
+}
+
+fn ship_pos(ship: &Ship, cell: &ShipCell) -> Pos {
+    ship.coord.offset(cell.coord.y, cell.coord.x)
+}
+
+// Cells off the field are left out
+pub fn add_ship(field: &mut Field, ship: &Ship) {
+    for i in ship.cells.iter() {
+        match field.cell_mut(ship_pos(ship, i)) {
+            Some(cell) => { cell.Type = i.cell; }
+            None => {}
+        }
+    }
+}
+
+fn register_ship(field: &mut Field, name: &str, orientation: Orientation, cells: Vec<Coord>, hits: usize) -> usize {
+    let id = field.ships.len();
+    for i in cells.iter() {
+        match field.cell_mut(i.pos()) {
+            Some(cell) => { cell.ship = Some(id); }
+            None => {}
+        }
+    }
+    field.ships.push(PlacedShip {
+        id : id,
+        name : name.to_string(),
+        orientation : orientation,
+        cells : cells,
+        hits : hits
+    });
+    return id;
+}
+
+// Adds the ship for good, so its cells can be told apart from other ships
+pub fn place_ship(field: &mut Field, ship: &Ship, name: &str) {
+    add_ship(field, ship);
+    let cells : Vec<Coord> = ship.cells.iter().filter_map(|i| field.coord(ship_pos(ship, i))).collect();
+    register_ship(field, name, ship.orientation, cells, 0);
+}
+
+// Registry rebuilt from the ship ids of the cells, names are left empty
+pub fn register_ships(field: &mut Field) {
+    let mut ships : Vec<PlacedShip> = vec![];
+    for i in 0..field.height {
+        for j in 0..field.width {
+            let id = match field.cells[i][j].ship {
+                Some(id) => id,
+                None => { continue; }
+            };
+            while ships.len() <= id {
+                let next = ships.len();
+                ships.push(PlacedShip { id : next, name : String::new(), orientation : UPRIGHT, cells : vec![], hits : 0 });
+            }
+            ships[id].cells.push(Coord { y : i as u32, x : j as u32 });
+            match field.cells[i][j].Type {
+                CellType::COLLISION_SHIP => { ships[id].hits += 1; }
+                _ => {}
+            }
+        }
+    }
+    field.ships = ships;
+}
+
+pub fn ship_at(field: &Field, y: usize, x: usize) -> Option<&PlacedShip> {
+    match field.cell(Pos { y : y as i32, x : x as i32 }).and_then(|cell| cell.ship) {
+        Some(id) if id < field.ships.len() => Some(&field.ships[id]),
+        _ => None
+    }
+}
+
+pub fn remove_ship(field: &mut Field, ship: &Ship) {
+    for i in ship.cells.iter() {
+        match field.cell_mut(ship_pos(ship, i)) {
+            Some(cell) => { cell.Type = CellType::EMPTY; }
+            None => {}
+        }
+    }
+}
+
+// Cells moved so the topmost and the leftmost ones are at 0, sorted by row
+pub fn normalize_shape(cells: &Vec<Coord>) -> Vec<Coord> {
+    let top = cells.iter().map(|cell| cell.y).min().unwrap_or(0);
+    let left = cells.iter().map(|cell| cell.x).min().unwrap_or(0);
+    let mut shape : Vec<Coord> = cells.iter().map(|cell| Coord { y : cell.y - top, x : cell.x - left }).collect();
+    shape.sort_by_key(|cell| (cell.y, cell.x));
+    return shape;
+}
+
+// Quarter turn clockwise of a normalized shape, the result is normalized too
+pub fn turn_shape(cells: &Vec<Coord>) -> Vec<Coord> {
+    let bottom = cells.iter().map(|cell| cell.y).max().unwrap_or(0);
+    cells.iter().map(|cell| Coord { y : cell.x, x : bottom - cell.y }).collect()
+}
+
+// Left to right mirror of a normalized shape
+pub fn mirror_shape(cells: &Vec<Coord>) -> Vec<Coord> {
+    let right = cells.iter().map(|cell| cell.x).max().unwrap_or(0);
+    cells.iter().map(|cell| Coord { y : cell.y, x : right - cell.x }).collect()
+}
+
+pub fn is_straight(cells: &Vec<Coord>) -> bool {
+    cells.iter().all(|cell| cell.y == cells[0].y) || cells.iter().all(|cell| cell.x == cells[0].x)
+}
+
+// Every different way the shape can lie, each one normalized
+pub fn shape_orientations(shape: &Vec<Coord>) -> Vec<(Orientation, Vec<Coord>)> {
+    let mut found : Vec<(Orientation, Vec<Coord>)> = vec![];
+    for &mirrored in [false, true].iter() {
+        let mut cells = normalize_shape(shape);
+        if mirrored {
+            cells = mirror_shape(&cells);
+        }
+        for turns in 0..4 {
+            let sorted = normalize_shape(&cells);
+            if !found.iter().any(|&(_, ref other)| *other == sorted) {
+                found.push((Orientation { turns : turns, mirrored : mirrored }, sorted));
+            }
+            cells = turn_shape(&cells);
+        }
+    }
+    return found;
+}
+
+// How the shape has to lie to cover exactly these cells, anywhere on the field
+pub fn find_orientation(shape: &Vec<Coord>, cells: &Vec<Coord>) -> Option<Orientation> {
+    let wanted = normalize_shape(cells);
+    shape_orientations(shape).into_iter().find(|&(_, ref other)| *other == wanted).map(|(orientation, _)| orientation)
+}
+
+// The cells of a ship are never left or above its corner
+pub fn ship_offsets(ship: &Ship) -> Vec<Coord> {
+    ship.cells.iter().map(|i| Coord { y : i.coord.y as u32, x : i.coord.x as u32 }).collect()
+}
+
+// Quarter turn clockwise, the ship keeps its top left corner
+pub fn rotate_ship(ship: &mut Ship) {
+    let turned = turn_shape(&ship_offsets(ship));
+    for (i, coord) in ship.cells.iter_mut().zip(turned) {
+        i.coord = coord.pos();
+    }
+    ship.orientation.turns = (ship.orientation.turns + 1) % 4;
+}
+
+// Turning back is the same as turning the other three quarters
+pub fn rotate_ship_back(ship: &mut Ship) {
+    for _ in 0..3 {
+        rotate_ship(ship);
+    }
+}
+
+pub fn mirror_ship(ship: &mut Ship) {
+    let mirrored = mirror_shape(&ship_offsets(ship));
+    for (i, coord) in ship.cells.iter_mut().zip(mirrored) {
+        i.coord = coord.pos();
+    }
+    // A mirrored turn is the opposite turn of the mirror
+    ship.orientation.mirrored = !ship.orientation.mirrored;
+    ship.orientation.turns = (4 - ship.orientation.turns) % 4;
+}
+
+// Why a ship can't go where it is
+#[derive(Copy, Clone, PartialEq)]
+pub enum Violation {
+    // Some of its cells are outside the field
+    OUT_OF_BOUNDS,
+    // Cell on top of another ship
+    OVERLAP(Coord),
+    // Cell next to another ship against the adjacency rule
+    TOUCHING(Coord),
+    // The fleet has only this many ships of its shape and all are placed
+    TOO_MANY(usize),
+}
+
+fn is_ship(cell: &Cell) -> bool {
+    match cell.Type {
+        CellType::SHIP | CellType::COLLISION_SHIP => true,
+        _ => false
+    }
+}
+
+fn touches_ship(field: &Field, y: usize, x: usize) -> bool {
+    for (dy, dx) in field.forbidden_around() {
+        let ny = y as i32 + dy;
+        let nx = x as i32 + dx;
+        if field.contains(ny, nx) && is_ship(&field.cells[ny as usize][nx as usize]) {
+            return true;
+        }
+    }
+
+    return false;
+}
+
+// Everything wrong with the ship where it is, against the ships on the field.
+// A ship sticking out of the field isn't checked any further
+pub fn ship_violations(field: &Field, ship: &Ship) -> Vec<Violation> {
+    let mut coords : Vec<Coord> = vec![];
+    for i in ship.cells.iter() {
+        match field.coord(ship_pos(ship, i)) {
+            Some(coord) => { coords.push(coord); }
+            None => { return vec![Violation::OUT_OF_BOUNDS]; }
+        }
+    }
+    let mut violations : Vec<Violation> = vec![];
+    for &coord in coords.iter() {
+        let cy = coord.y as usize;
+        let cx = coord.x as usize;
+        if is_ship(&field.cells[cy][cx]) {
+            violations.push(Violation::OVERLAP(coord));
+        }
+        else if touches_ship(field, cy, cx) {
+            violations.push(Violation::TOUCHING(coord));
+        }
+    }
+    return violations;
+}
+
+// True if the ship sticks out of the field, it can't be placed then. Otherwise
+// its cells in the way of other ships are marked as COLLISION_SHIP for drawing,
+// see `ship_violations`
+pub fn collision(field: &Field, ship: &mut Ship) -> bool {
+    let violations = ship_violations(field, ship);
+    if violations.contains(&Violation::OUT_OF_BOUNDS) {
+        ship.can_be_placed = false;
+        return true;
+    }
+    let corner = ship.coord;
+    for i in ship.cells.iter_mut() {
+        let pos = corner.offset(i.coord.y, i.coord.x);
+        let coord = Coord { y : pos.y as u32, x : pos.x as u32 };
+        if violations.contains(&Violation::OVERLAP(coord)) || violations.contains(&Violation::TOUCHING(coord)) {
+            i.cell = CellType::COLLISION_SHIP;
+        }
+        else {
+            i.cell = CellType::SHIP;
+        }
+    }
+    ship.can_be_placed = violations.len() == 0;
+
+    return false;
+}
+
+pub fn remember_before(field: &Field, ship: &Ship, before: &mut Vec<ShipCell>) {
+    before.clear();
+    for i in ship.cells.iter() {
+        match field.coord(ship_pos(ship, i)) {
+            Some(coord) => { before.push(ShipCell { coord : coord.pos(), cell : field.cells[coord.y as usize][coord.x as usize].Type }); }
+            None => {}
+        }
+    }
+}
+
+pub fn place_before(field: &mut Field, before: &Vec<ShipCell>) {
+    for i in before.iter() {
+        match field.cell_mut(i.coord) {
+            Some(cell) => { cell.Type = i.cell; }
+            None => {}
+        }
+    }
+}
+
+pub fn opponent_view(field: &Field) -> OpponentView {
+    let mut cells = vec![vec![Knowledge::UNKNOWN; field.width]; field.height];
+    for i in 0..field.height {
+        for j in 0..field.width {
+            let sunk = match ship_at(field, i, j) {
+                Some(ship) => ship.is_sunk(),
+                None => false
+            };
+            cells[i][j] = match field.cells[i][j].Type {
+                CellType::SHOT => Knowledge::MISS,
+                CellType::COLLISION_SHIP if sunk => Knowledge::SUNK,
+                CellType::COLLISION_SHIP => Knowledge::HIT,
+                _ => Knowledge::UNKNOWN
+            };
+        }
+    }
+    OpponentView {
+        width : field.width,
+        height : field.height,
+        adjacency : field.adjacency,
+        cells : cells
+    }
+}
+
+// Field with nothing but what the view knows, for drawing it
+pub fn known_field(view: &OpponentView) -> Field {
+    let mut field = empty_field(view.width, view.height, view.adjacency);
+    for i in 0..view.height {
+        for j in 0..view.width {
+            field.cells[i][j].Type = match view.cells[i][j] {
+                Knowledge::UNKNOWN => CellType::EMPTY,
+                Knowledge::MISS => CellType::SHOT,
+                Knowledge::HIT | Knowledge::SUNK => CellType::COLLISION_SHIP
+            };
+        }
+    }
+    return field;
+}
+
+pub fn tryToShot(field: &mut Field, pos: Pos) -> ShotResult {
+    let ship = match field.cell_mut(pos) {
+        Some(cell) => match cell.Type {
+            CellType::SHOT | CellType::COLLISION_SHIP => {
+                return ShotResult::ALREADY_SHOT;
+            }
+            CellType::SHIP => {
+                cell.Type = CellType::COLLISION_SHIP;
+                cell.ship
+            }
+            _ => {
+                cell.Type = CellType::SHOT;
+                return ShotResult::MISS;
+            }
+        },
+        None => { return ShotResult::OUT_OF_BOUNDS; }
+    };
+
+    // The hit goes to the ship in the registry, a cell without one is a ship of its own
+    let cells = match ship {
+        Some(id) if id < field.ships.len() => {
+            field.ships[id].hits += 1;
+            if !field.ships[id].is_sunk() {
+                return ShotResult::HIT;
+            }
+            field.ships[id].cells.clone()
+        }
+        _ => {
+            let cells = vec![Coord { y : pos.y as u32, x : pos.x as u32 }];
+            register_ship(field, "", UPRIGHT, cells.clone(), 1);
+            cells
+        }
+    };
+
+    mark_around_sunk(field, &cells);
+    return ShotResult::SUNK(cells);
+}
+
+// Other ships can't be next to a sunk ship, so these cells are misses
+pub fn mark_around_sunk(field: &mut Field, cells: &Vec<Coord>) {
+    let around = field.forbidden_around();
+    for i in cells.iter() {
+        for &(dy, dx) in around.iter() {
+            let ny = i.y as i32 + dy;
+            let nx = i.x as i32 + dx;
+            if !field.contains(ny, nx) {
+                continue;
+            }
+            match field.cells[ny as usize][nx as usize].Type {
+                CellType::EMPTY => {
+                    field.cells[ny as usize][nx as usize].Type = CellType::SHOT;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// Marks a shot resolved by the other side, who alone knows where its ships are
+pub fn apply_result(field: &mut Field, y: u32, x: u32, result: &ShotResult) {
+    if !field.contains(y as i32, x as i32) {
+        return;
+    }
+    match *result {
+        ShotResult::MISS => {
+            field.cells[y as usize][x as usize].Type = CellType::SHOT;
+        }
+        ShotResult::HIT => {
+            field.cells[y as usize][x as usize].Type = CellType::COLLISION_SHIP;
+        }
+        ShotResult::SUNK(ref cells) => {
+            let inside : Vec<Coord> = cells.iter().cloned().filter(|i| field.contains(i.y as i32, i.x as i32)).collect();
+            for i in inside.iter() {
+                field.cells[i.y as usize][i.x as usize].Type = CellType::COLLISION_SHIP;
+            }
+            let hits = inside.len();
+            register_ship(field, "", UPRIGHT, inside, hits);
+            mark_around_sunk(field, cells);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // L of four cells, upright at the top left of the field
+    fn l_ship() -> Ship {
+        let cells = [(0, 0), (1, 0), (2, 0), (2, 1)];
+        Ship {
+            coord : Pos { y : 0, x : 0 },
+            cells : cells.iter().map(|&(y, x)| ShipCell { coord : Pos { y : y, x : x }, cell : CellType::SHIP }).collect(),
+            orientation : UPRIGHT,
+            can_be_placed : true
+        }
+    }
+
+    fn ship_shape(ship: &Ship) -> Vec<Coord> {
+        normalize_shape(&ship_offsets(ship))
+    }
+
+    // The upright shape put the way the orientation says
+    fn oriented(shape: &Vec<Coord>, orientation: Orientation) -> Vec<Coord> {
+        let mut cells = normalize_shape(shape);
+        if orientation.mirrored {
+            cells = mirror_shape(&cells);
+        }
+        for _ in 0..orientation.turns {
+            cells = turn_shape(&cells);
+        }
+        return normalize_shape(&cells);
+    }
+
+    #[test]
+    fn four_turns_give_back_the_ship() {
+        let mut ship = l_ship();
+        let start = ship_shape(&ship);
+        for turns in 1..5 {
+            rotate_ship(&mut ship);
+            assert_eq!(ship.orientation.turns, turns % 4);
+            assert!((ship_shape(&ship) == start) == (turns == 4));
+        }
+        assert!(ship.orientation == UPRIGHT);
+    }
+
+    #[test]
+    fn turning_back_undoes_a_turn() {
+        let mut ship = l_ship();
+        let start = ship_shape(&ship);
+        rotate_ship(&mut ship);
+        rotate_ship_back(&mut ship);
+        assert!(ship_shape(&ship) == start);
+        assert!(ship.orientation == UPRIGHT);
+    }
+
+    #[test]
+    fn orientation_follows_mirrors_and_turns() {
+        let mut ship = l_ship();
+        let shape = ship_shape(&ship);
+        // Mirrors between turns, the orientation has to describe the cells after each step
+        for step in 0..12 {
+            if step % 3 == 0 {
+                mirror_ship(&mut ship);
+            } else {
+                rotate_ship(&mut ship);
+            }
+            assert!(ship_shape(&ship) == oriented(&shape, ship.orientation));
+            assert!(find_orientation(&shape, &ship_shape(&ship)) == Some(ship.orientation));
+        }
+        mirror_ship(&mut ship);
+        mirror_ship(&mut ship);
+        assert!(ship_shape(&ship) == oriented(&shape, ship.orientation));
+    }
+}
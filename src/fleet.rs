@@ -0,0 +1,379 @@
+use field::*;
+use save::parse_number;
+
+#[derive(Clone)]
+pub struct ShipType {
+    pub name: String,
+    // Normalized cells of the upright ship, see `normalize_shape`
+    pub shape: Vec<Coord>,
+    pub count: usize
+}
+
+impl ShipType {
+    pub fn length(&self) -> usize {
+        self.shape.len()
+    }
+}
+
+#[derive(Clone)]
+pub struct Fleet {
+    pub name: String,
+    pub ships: Vec<ShipType>
+}
+
+// Vertical line of cells
+pub fn straight_shape(length: usize) -> Vec<Coord> {
+    (0..length).map(|i| Coord { y : i as u32, x : 0 }).collect()
+}
+
+fn ship_type(name: &str, length: usize, count: usize) -> ShipType {
+    ShipType { name : name.to_string(), shape : straight_shape(length), count : count }
+}
+
+// Cells given as (y, x) pairs
+fn shaped_type(name: &str, cells: &[(u32, u32)], count: usize) -> ShipType {
+    let shape = cells.iter().map(|&(y, x)| Coord { y : y, x : x }).collect();
+    ShipType { name : name.to_string(), shape : normalize_shape(&shape), count : count }
+}
+
+// One 4-deck, two 3-deck, three 2-deck and four 1-deck ships
+pub fn russian_fleet() -> Fleet {
+    Fleet {
+        name : "Russian".to_string(),
+        ships : vec![ ship_type("Battleship", 4, 1),
+                      ship_type("Cruiser", 3, 2),
+                      ship_type("Destroyer", 2, 3),
+                      ship_type("Torpedo boat", 1, 4)]
+    }
+}
+
+pub fn milton_bradley_fleet() -> Fleet {
+    Fleet {
+        name : "Milton Bradley".to_string(),
+        ships : vec![ ship_type("Carrier", 5, 1),
+                      ship_type("Battleship", 4, 1),
+                      ship_type("Cruiser", 3, 1),
+                      ship_type("Submarine", 3, 1),
+                      ship_type("Destroyer", 2, 1)]
+    }
+}
+
+// Plus, L, T and S shaped ships besides straight ones
+pub fn polyomino_fleet() -> Fleet {
+    Fleet {
+        name : "Polyomino".to_string(),
+        ships : vec![ shaped_type("Flagship", &[(0, 1), (1, 0), (1, 1), (1, 2), (2, 1)], 1),
+                      shaped_type("Battleship", &[(0, 0), (1, 0), (2, 0), (2, 1)], 1),
+                      shaped_type("Cruiser", &[(0, 0), (0, 1), (0, 2), (1, 1)], 1),
+                      shaped_type("Frigate", &[(0, 1), (0, 2), (1, 0), (1, 1)], 1),
+                      ship_type("Destroyer", 2, 2),
+                      ship_type("Torpedo boat", 1, 2)]
+    }
+}
+
+pub fn builtin_fleets() -> Vec<Fleet> {
+    vec![russian_fleet(), milton_bradley_fleet(), polyomino_fleet()]
+}
+
+// Every ship of the fleet in placement order
+pub fn fleet_ships(fleet: &Fleet) -> Vec<ShipType> {
+    let mut ships : Vec<ShipType> = vec![];
+    for i in fleet.ships.iter() {
+        for _ in 0..i.count {
+            ships.push(i.clone());
+        }
+    }
+    return ships;
+}
+
+pub fn fleet_shapes(fleet: &Fleet) -> Vec<Vec<Coord>> {
+    fleet_ships(fleet).into_iter().map(|ship| ship.shape).collect()
+}
+
+// A fleet from a file or the network has to fit the field at all, or even
+// listing its ships could take all the memory
+pub fn check_fleet(fleet: &Fleet, width: usize, height: usize) -> Result<(), String> {
+    let cells = width * height;
+    let mut total = 0;
+    for ship in fleet.ships.iter() {
+        let tall = ship.shape.iter().map(|cell| cell.y as usize + 1).max().unwrap_or(0);
+        let wide = ship.shape.iter().map(|cell| cell.x as usize + 1).max().unwrap_or(0);
+        if ship.length() == 0 || (tall > height || wide > width) && (tall > width || wide > height) {
+            return Err(format!("{} doesn't fit a {}x{} field", ship.name, width, height));
+        }
+        if ship.count > cells {
+            return Err(format!("Too many ships of type {}", ship.name));
+        }
+        total += ship.count * ship.length();
+        if total > cells {
+            return Err(format!("Fleet has more cells than a {}x{} field", width, height));
+        }
+    }
+    return Ok(());
+}
+
+pub fn fleet_size(fleet: &Fleet) -> usize {
+    fleet.ships.iter().fold(0, |size, ship| size + ship.count)
+}
+
+// Upright ship with the top left corner of its shape at coord
+pub fn shaped_ship(shape: &Vec<Coord>, coord: Coord) -> Ship {
+    Ship {
+        coord : coord.pos(),
+        can_be_placed: true,
+        orientation : UPRIGHT,
+        cells : shape.iter().map(|&offset| ShipCell { coord : offset.pos(), cell : CellType::SHIP }).collect()
+    }
+}
+
+// Straight ships are written as their length, other shapes as their cells
+pub fn shape_string(shape: &Vec<Coord>) -> String {
+    if normalize_shape(shape) == straight_shape(shape.len()) {
+        return shape.len().to_string();
+    }
+    let cells : Vec<String> = normalize_shape(shape).iter().map(|cell| format!("{},{}", cell.y, cell.x)).collect();
+    return cells.join(";");
+}
+
+// Reads what `shape_string` writes. Cells have to be connected
+pub fn parse_shape(token: Option<&str>, line: &str) -> Result<Vec<Coord>, String> {
+    let token = match token {
+        Some(token) => token,
+        None => { return Err(format!("Missing ship shape in '{}'", line)); }
+    };
+    let cells = if token.contains(',') {
+        let cells = match try!(parse_layout(token)).pop() {
+            Some(cells) => cells,
+            None => vec![]
+        };
+        // Nothing bigger fits any field, and the connectivity check below is slow on huge shapes
+        if cells.len() > MAX_FIELD_SIZE || cells.iter().any(|cell| cell.y as usize >= MAX_FIELD_SIZE || cell.x as usize >= MAX_FIELD_SIZE) {
+            return Err(format!("Ship shape is too big in '{}'", line));
+        }
+        normalize_shape(&cells)
+    } else {
+        let length = try!(parse_number(Some(token), line));
+        // Nothing longer fits any field
+        if length > MAX_FIELD_SIZE {
+            return Err(format!("Ship is too long in '{}'", line));
+        }
+        straight_shape(length)
+    };
+    if cells.len() == 0 {
+        return Err(format!("Empty ship shape in '{}'", line));
+    }
+    // Sorted by `normalize_shape`, so a cell given twice is next to itself
+    if cells.windows(2).any(|pair| pair[0] == pair[1]) {
+        return Err(format!("Ship shape has a cell twice in '{}'", line));
+    }
+    let mut connected = vec![cells[0]];
+    let mut k = 0;
+    while k < connected.len() {
+        let cell = connected[k];
+        for other in cells.iter() {
+            let next_to = (cell.y as i64 - other.y as i64).abs() + (cell.x as i64 - other.x as i64).abs() == 1;
+            if next_to && !connected.contains(other) {
+                connected.push(*other);
+            }
+        }
+        k += 1;
+    }
+    if connected.len() != cells.len() {
+        return Err(format!("Ship shape isn't connected in '{}'", line));
+    }
+    return Ok(cells);
+}
+
+// "<shape> <count> <name>" of save files and network games
+pub fn ship_line(ship: &ShipType) -> String {
+    format!("{} {} {}", shape_string(&ship.shape), ship.count, ship.name)
+}
+
+pub fn parse_ship_line<'a, I: Iterator<Item = &'a str>>(tokens: &mut I, line: &str) -> Result<ShipType, String> {
+    let shape = try!(parse_shape(tokens.next(), line));
+    let count = try!(parse_number(tokens.next(), line));
+    let name = tokens.collect::<Vec<&str>>().join(" ");
+    Ok(ShipType { name : name, shape : shape, count : count })
+}
+
+// Cells of every placed ship in the order of their ids, as "y,x;y,x... y,x;..."
+pub fn layout_string(field: &Field) -> String {
+    let mut ships : Vec<String> = vec![];
+    for ship in field.ships.iter() {
+        let mut cells = ship.cells.clone();
+        cells.sort_by_key(|cell| (cell.y, cell.x));
+        let cells : Vec<String> = cells.iter().map(|cell| format!("{},{}", cell.y, cell.x)).collect();
+        ships.push(cells.join(";"));
+    }
+    return ships.join(" ");
+}
+
+// Names registered ships by their shape, in the order the fleet is placed
+pub fn name_ships(field: &mut Field, fleet: &Fleet) {
+    let mut unused = fleet_ships(fleet);
+    for ship in field.ships.iter_mut() {
+        let found = unused.iter().enumerate()
+            .filter_map(|(index, kind)| find_orientation(&kind.shape, &ship.cells).map(|orientation| (index, orientation)))
+            .next();
+        match found {
+            Some((index, orientation)) => {
+                ship.name = unused.remove(index).name;
+                ship.orientation = orientation;
+            }
+            None => {}
+        }
+    }
+}
+
+pub fn parse_layout(layout: &str) -> Result<Vec<Vec<Coord>>, String> {
+    let mut ships : Vec<Vec<Coord>> = vec![];
+    for ship in layout.split_whitespace() {
+        let mut cells : Vec<Coord> = vec![];
+        for cell in ship.split(';') {
+            let mut parts = cell.split(',');
+            let y = try!(parse_number(parts.next(), layout));
+            let x = try!(parse_number(parts.next(), layout));
+            cells.push(Coord { y : y, x : x });
+        }
+        ships.push(cells);
+    }
+    return Ok(ships);
+}
+
+// Everything that keeps the ship from being placed, see `Violation`. Nothing
+// is changed, the ship may be anywhere, even partly outside the field
+pub fn validate_placement(field: &Field, ship: &Ship, fleet: &Fleet) -> Vec<Violation> {
+    let mut violations = ship_violations(field, ship);
+    let cells = ship_offsets(ship);
+    let allowed = fleet.ships.iter()
+        .filter(|kind| find_orientation(&kind.shape, &cells).is_some())
+        .fold(0, |allowed, kind| allowed + kind.count);
+    let placed = field.ships.iter().filter(|placed| find_orientation(&placed.cells, &cells).is_some()).count();
+    if placed >= allowed {
+        violations.push(Violation::TOO_MANY(allowed));
+    }
+    return violations;
+}
+
+pub fn violation_message(violation: &Violation) -> String {
+    match *violation {
+        Violation::OUT_OF_BOUNDS => "The ship doesn't fit on the field.".to_string(),
+        Violation::OVERLAP(_) => "It's on top of another ship.".to_string(),
+        Violation::TOUCHING(_) => "It's too close to another ship.".to_string(),
+        Violation::TOO_MANY(0) => "The fleet has no ship of this shape.".to_string(),
+        Violation::TOO_MANY(count) => format!("The fleet has only {} ship(s) of this shape.", count)
+    }
+}
+
+// Every kind of violation once, in the order they were found
+pub fn violations_message(violations: &Vec<Violation>) -> String {
+    let mut messages : Vec<String> = vec![];
+    for violation in violations.iter() {
+        let message = violation_message(violation);
+        if !messages.contains(&message) {
+            messages.push(message);
+        }
+    }
+    return messages.join(" ");
+}
+
+// Field with the listed ships placed, if they follow the rules and make up the fleet.
+// Placement is checked the same way as on the placement screen.
+pub fn layout_field(ships: &Vec<Vec<Coord>>, width: usize, height: usize, adjacency: Adjacency, fleet: &Fleet) -> Result<Field, String> {
+    let mut field = empty_field(width, height, adjacency);
+    for cells in ships.iter() {
+        for cell in cells.iter() {
+            if !field.contains(cell.y as i32, cell.x as i32) {
+                return Err(format!("ship cell {},{} is outside the field", cell.y, cell.x));
+            }
+        }
+        let top = cells.iter().map(|cell| cell.y).min().unwrap_or(0);
+        let left = cells.iter().map(|cell| cell.x).min().unwrap_or(0);
+        let offsets : Vec<Coord> = cells.iter().map(|cell| Coord { y : cell.y - top, x : cell.x - left }).collect();
+        let ship = shaped_ship(&offsets, Coord { y : top, x : left });
+        let violations = validate_placement(&field, &ship, fleet);
+        if violations.len() != 0 {
+            return Err(format!("ship at {},{}: {}", top, left, violations_message(&violations)));
+        }
+        place_ship(&mut field, &ship, "");
+    }
+    if field.ships.len() != fleet_size(fleet) {
+        return Err("revealed ships aren't the agreed fleet".to_string());
+    }
+    name_ships(&mut field, fleet);
+    return Ok(field);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn straight_ship(length: usize, y: i32, x: i32) -> Ship {
+        let mut ship = shaped_ship(&straight_shape(length), Coord { y : 0, x : 0 });
+        ship.coord = Pos { y : y, x : x };
+        return ship;
+    }
+
+    // Russian fleet with its battleship placed at the top left corner
+    fn field_with_battleship() -> Field {
+        let mut field = empty_field(10, 10, Adjacency::NO_TOUCHING);
+        place_ship(&mut field, &straight_ship(4, 0, 0), "Battleship");
+        return field;
+    }
+
+    #[test]
+    fn free_ship_has_no_violations() {
+        let field = field_with_battleship();
+        assert!(validate_placement(&field, &straight_ship(3, 0, 5), &russian_fleet()).is_empty());
+    }
+
+    #[test]
+    fn ship_off_the_field_is_out_of_bounds() {
+        let field = field_with_battleship();
+        assert!(validate_placement(&field, &straight_ship(3, 8, 5), &russian_fleet()) == vec![Violation::OUT_OF_BOUNDS]);
+        assert!(validate_placement(&field, &straight_ship(3, -1, 5), &russian_fleet()) == vec![Violation::OUT_OF_BOUNDS]);
+    }
+
+    #[test]
+    fn ship_on_another_one_overlaps() {
+        let field = field_with_battleship();
+        let violations = validate_placement(&field, &straight_ship(2, 3, 0), &russian_fleet());
+        assert!(violations == vec![Violation::OVERLAP(Coord { y : 3, x : 0 }), Violation::TOUCHING(Coord { y : 4, x : 0 })]);
+    }
+
+    #[test]
+    fn ship_next_to_another_one_touches_it() {
+        let field = field_with_battleship();
+        let violations = validate_placement(&field, &straight_ship(2, 4, 1), &russian_fleet());
+        assert!(violations == vec![Violation::TOUCHING(Coord { y : 4, x : 1 })]);
+        // Corners are fine when the rules allow it
+        let mut field = empty_field(10, 10, Adjacency::CORNERS_ALLOWED);
+        place_ship(&mut field, &straight_ship(4, 0, 0), "Battleship");
+        assert!(validate_placement(&field, &straight_ship(2, 4, 1), &russian_fleet()).is_empty());
+    }
+
+    #[test]
+    fn ship_beyond_the_fleet_is_too_many() {
+        let field = field_with_battleship();
+        assert!(validate_placement(&field, &straight_ship(4, 0, 5), &russian_fleet()) == vec![Violation::TOO_MANY(1)]);
+        assert!(validate_placement(&field, &straight_ship(5, 0, 5), &russian_fleet()) == vec![Violation::TOO_MANY(0)]);
+    }
+
+    #[test]
+    fn shapes_are_parsed_and_normalized() {
+        let shape = parse_shape(Some("5,5;6,5;7,5;7,6"), "").unwrap();
+        assert!(shape == vec![Coord { y : 0, x : 0 }, Coord { y : 1, x : 0 }, Coord { y : 2, x : 0 }, Coord { y : 2, x : 1 }]);
+        assert!(parse_shape(Some("3"), "").unwrap() == straight_shape(3));
+    }
+
+    #[test]
+    fn broken_shapes_are_rejected() {
+        assert!(parse_shape(Some("0,0;1,1"), "").is_err());
+        assert!(parse_shape(Some("0,0;0,0"), "").is_err());
+        assert!(parse_shape(Some("0,0;0,4294967295"), "").is_err());
+        assert!(parse_shape(Some("0,25;0,26"), "").is_err());
+        assert!(parse_shape(Some("27"), "").is_err());
+        let cells : Vec<String> = (0..MAX_FIELD_SIZE + 1).map(|i| format!("{},{}", i / 2, i % 2)).collect();
+        assert!(parse_shape(Some(&cells.join(";")), "").is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use std::cmp;
+
+use field::*;
+use fleet::*;
+use rng::*;
+
+#[derive(Copy, Clone)]
+pub enum GameMode {
+    CLASSIC,
+    // One shot for every ship still afloat
+    SALVO,
+    // Same number of shots every turn
+    FIXED_SALVO(usize),
+}
+
+// Who plays the enemy fleet
+#[derive(Copy, Clone, PartialEq)]
+pub enum Opponent {
+    COMPUTER,
+    // Second player at the same keyboard
+    HUMAN,
+    // Player on another machine, see `net`
+    REMOTE,
+    // Programs play both sides, see `headless`
+    BOT,
+}
+
+// AI is the side of the enemy fleet, even when a human plays it
+#[derive(Copy, Clone, PartialEq)]
+pub enum Turn {
+    PLAYER,
+    AI,
+}
+
+pub fn other_turn(turn: Turn) -> Turn {
+    match turn {
+        Turn::PLAYER => Turn::AI,
+        Turn::AI => Turn::PLAYER
+    }
+}
+
+#[derive(Clone)]
+pub struct ShotRecord {
+    pub turn: Turn,
+    pub coord: Coord,
+    pub result: ShotResult
+}
+
+// Everything needed to continue a game after both fleets are placed
+pub struct Game {
+    pub mode: GameMode,
+    pub opponent: Opponent,
+    pub fleet: Fleet,
+    pub userfield: Field,
+    pub enemyfield: Field,
+    pub turn: Turn,
+    pub history: Vec<ShotRecord>,
+    pub rng: GameRng
+}
+
+pub fn new_game(width: usize, height: usize, adjacency: Adjacency, fleet: &Fleet, mode: GameMode, opponent: Opponent, seed: u64) -> Game {
+    Game {
+        mode : mode,
+        opponent : opponent,
+        fleet : fleet.clone(),
+        userfield : empty_field(width, height, adjacency),
+        enemyfield : empty_field(width, height, adjacency),
+        turn : Turn::PLAYER,
+        history : vec![],
+        rng : seeded_rng(seed)
+    }
+}
+
+// Number of shots a player with `surviving` ships fires per turn
+pub fn salvo_size(mode: GameMode, surviving: usize) -> usize {
+    match mode {
+        GameMode::CLASSIC => 1,
+        GameMode::SALVO => surviving,
+        GameMode::FIXED_SALVO(shots) => shots
+    }
+}
+
+pub fn unshot_cells(field: &Field) -> usize {
+    let mut count = 0;
+    for i in 0..field.height {
+        for j in 0..field.width {
+            let shot = match field.cell(Pos { y : i as i32, x : j as i32 }) {
+                Some(cell) => match cell.Type {
+                    CellType::SHOT | CellType::COLLISION_SHIP => true,
+                    _ => false
+                },
+                None => true
+            };
+            if !shot {
+                count += 1;
+            }
+        }
+    }
+    return count;
+}
+
+// Ships of the field's registry that are sunk. A remote fleet only has its sunk
+// ships in it, ids a loaded field skips leave ships without cells
+pub fn sunk_ships(field: &Field) -> usize {
+    field.ships.iter().filter(|ship| ship.cells.len() > 0 && ship.is_sunk()).count()
+}
+
+impl Game {
+    pub fn player_won(&self) -> bool {
+        sunk_ships(&self.enemyfield) >= fleet_size(&self.fleet)
+    }
+
+    pub fn ai_won(&self) -> bool {
+        sunk_ships(&self.userfield) >= fleet_size(&self.fleet)
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.player_won() || self.ai_won()
+    }
+
+    pub fn player_salvo_size(&self) -> usize {
+        let shots = salvo_size(self.mode, fleet_size(&self.fleet).saturating_sub(sunk_ships(&self.userfield)));
+        cmp::min(shots, unshot_cells(&self.enemyfield))
+    }
+
+    pub fn ai_salvo_size(&self) -> usize {
+        let shots = salvo_size(self.mode, fleet_size(&self.fleet).saturating_sub(sunk_ships(&self.enemyfield)));
+        cmp::min(shots, unshot_cells(&self.userfield))
+    }
+
+    pub fn salvo_size_of(&self, turn: Turn) -> usize {
+        match turn {
+            Turn::PLAYER => self.player_salvo_size(),
+            Turn::AI => self.ai_salvo_size()
+        }
+    }
+
+    // Own field of the side and the field it shoots at
+    pub fn fields_of(&self, turn: Turn) -> (&Field, &Field) {
+        match turn {
+            Turn::PLAYER => (&self.userfield, &self.enemyfield),
+            Turn::AI => (&self.enemyfield, &self.userfield)
+        }
+    }
+
+    // Shots fired and hits of one side
+    pub fn shot_stats(&self, turn: Turn) -> (u32, u32) {
+        let mut fired = 0;
+        let mut hit = 0;
+        for shot in self.history.iter().filter(|shot| shot.turn == turn) {
+            fired += 1;
+            match shot.result {
+                ShotResult::HIT | ShotResult::SUNK(_) => { hit += 1; }
+                _ => {}
+            }
+        }
+        return (fired, hit);
+    }
+
+    // Every shot the game loop in `player` resolves ends up here, shots
+    // that didn't count are left out
+    pub fn record_shot(&mut self, turn: Turn, coord: Coord, result: &ShotResult) {
+        match *result {
+            ShotResult::ALREADY_SHOT | ShotResult::OUT_OF_BOUNDS => { return; }
+            _ => {}
+        }
+        self.history.push(ShotRecord { turn : turn, coord : coord, result : result.clone() });
+    }
+}
@@ -0,0 +1,176 @@
+// Games between programs, without any terminal. Built-in AIs and bots (see
+// `bot`) can play on either side. A bot gets these lines on its stdin:
+//
+//   PLAYER 1|2                        side of the bot, player 1 shoots first
+//   RULES ... FLEET ... SHIP ... END  rules of the game, as in `net`
+//   PLACE                             answer: SHIPS <ship> ...
+//   TURN <shots>                      answer: <shots> lines SHOT <y> <x>
+//   RESULT <y> <x> miss|hit|sunk [<y>,<x> ...]
+//                                     outcome of each own shot, sunk lists the ship
+//   RESULT <y> <x> already-shot       the cell turned out to be shot earlier in the
+//                                     same salvo, the shot doesn't count and another
+//                                     TURN asks for the missing shots
+//   INCOMING <y> <x> miss|hit|sunk [<y>,<x> ...]
+//                                     opponent's shot at the own fleet
+//   ILLEGAL <reason>                  the whole last answer is rejected, send it again
+//   OVER win|lose                     end of the game, stdin is closed after it
+//
+// Ships are written as in network games, "<y>,<x>;<y>,<x>..." for every ship.
+// A shot is illegal outside the field, at a cell already shot or twice in one
+// salvo. Cells where the adjacency rule forbids ships next to a sunk one
+// count as shot. A bot forfeits the game if it doesn't answer in time, exits, or gets
+// its answer rejected three times in a row.
+
+use field::*;
+use fleet::*;
+use game::*;
+use bot::*;
+use net::*;
+use rng::*;
+use save::*;
+use player::*;
+
+static MAX_ILLEGAL: usize = 3;
+
+pub struct MatchResult {
+    pub winner: Turn,
+    // Why the loser forfeited, if it did
+    pub forfeit: Option<String>,
+    pub game: Game
+}
+
+fn outcome_line(coord: Coord, result: &ShotResult) -> String {
+    match *result {
+        ShotResult::SUNK(ref cells) => {
+            let cells : Vec<String> = cells.iter().map(|cell| format!("{},{}", cell.y, cell.x)).collect();
+            format!("{} {} sunk {}", coord.y, coord.x, cells.join(" "))
+        }
+        _ => format!("{} {} {}", coord.y, coord.x, outcome_name(result))
+    }
+}
+
+fn parse_ships(line: &str, own: &Field, fleet: &Fleet) -> Result<Field, String> {
+    let mut tokens = line.split_whitespace();
+    if tokens.next() != Some("SHIPS") {
+        return Err(format!("expected SHIPS, got '{}'", line));
+    }
+    let ships = try!(parse_layout(&tokens.collect::<Vec<&str>>().join(" ")));
+    layout_field(&ships, own.width, own.height, own.adjacency, fleet)
+}
+
+fn parse_shot_line(line: &str, target: &OpponentView, chosen: &Vec<Coord>) -> Result<Coord, String> {
+    let mut tokens = line.split_whitespace();
+    if tokens.next() != Some("SHOT") {
+        return Err(format!("expected SHOT, got '{}'", line));
+    }
+    let y : i32 = try!(parse_number(tokens.next(), line));
+    let x : i32 = try!(parse_number(tokens.next(), line));
+    if !target.contains(y, x) {
+        return Err(format!("{},{} is outside the field", y, x));
+    }
+    let coord = Coord { y : y as u32, x : x as u32 };
+    if target.knowledge(coord.pos()) != Some(Knowledge::UNKNOWN) {
+        return Err(format!("{},{} is already shot", y, x));
+    }
+    if chosen.contains(&coord) {
+        return Err(format!("{},{} is shot twice", y, x));
+    }
+    return Ok(coord);
+}
+
+impl Player for Bot {
+    fn name(&self) -> String {
+        self.command.clone()
+    }
+
+    fn start(&mut self, rules: &Rules, side: Turn) -> Result<(), Failure> {
+        let player = if side == Turn::PLAYER { 1 } else { 2 };
+        try!(self.send(&format!("PLAYER {}", player)).map_err(Failure::ERROR));
+        try!(self.send(&format_message(&Message::RULES(rules.width, rules.height, rules.adjacency, rules.mode))).map_err(Failure::ERROR));
+        try!(self.send(&format_message(&Message::FLEET(rules.fleet.name.clone()))).map_err(Failure::ERROR));
+        for ship in rules.fleet.ships.iter() {
+            try!(self.send(&format_message(&Message::SHIP(ship.clone()))).map_err(Failure::ERROR));
+        }
+        self.send(&format_message(&Message::END)).map_err(Failure::ERROR)
+    }
+
+    fn place_fleet(&mut self, own: &mut Field, fleet: &Fleet, _rng: &mut GameRng) -> Result<(), Failure> {
+        try!(self.send("PLACE").map_err(Failure::ERROR));
+        let mut reason = String::new();
+        for _ in 0..MAX_ILLEGAL {
+            let line = try!(self.receive().map_err(Failure::ERROR));
+            match parse_ships(&line, own, fleet) {
+                Ok(placed) => {
+                    *own = placed;
+                    return Ok(());
+                }
+                Err(e) => {
+                    reason = e;
+                    try!(self.send(&format!("ILLEGAL {}", reason)).map_err(Failure::ERROR));
+                }
+            }
+        }
+        Err(Failure::ERROR(format!("illegal placement: {}", reason)))
+    }
+
+    fn choose_shots(&mut self, _own: &Field, target: &OpponentView, shots: usize, _rng: &mut GameRng) -> Result<Vec<Coord>, Failure> {
+        try!(self.send(&format!("TURN {}", shots)).map_err(Failure::ERROR));
+        let mut reason = String::new();
+        for _ in 0..MAX_ILLEGAL {
+            // The whole salvo is read before it's checked
+            let mut lines : Vec<String> = vec![];
+            for _ in 0..shots {
+                lines.push(try!(self.receive().map_err(Failure::ERROR)));
+            }
+            let mut targets : Vec<Coord> = vec![];
+            for line in lines.iter() {
+                match parse_shot_line(line, target, &targets) {
+                    Ok(coord) => { targets.push(coord); }
+                    Err(e) => {
+                        reason = e;
+                        break;
+                    }
+                }
+            }
+            if targets.len() == shots {
+                return Ok(targets);
+            }
+            try!(self.send(&format!("ILLEGAL {}", reason)).map_err(Failure::ERROR));
+        }
+        Err(Failure::ERROR(format!("illegal shot: {}", reason)))
+    }
+
+    fn observe(&mut self, _own: &Field, _target: &OpponentView, coord: Coord, result: &ShotResult) -> Result<(), Failure> {
+        self.send(&format!("RESULT {}", outcome_line(coord, result))).map_err(Failure::ERROR)
+    }
+
+    fn incoming(&mut self, _own: &Field, _target: &OpponentView, coord: Coord, result: &ShotResult) -> Result<(), Failure> {
+        self.send(&format!("INCOMING {}", outcome_line(coord, result))).map_err(Failure::ERROR)
+    }
+
+    fn game_over(&mut self, won: bool) -> Result<(), Failure> {
+        let _ = self.send(if won { "OVER win" } else { "OVER lose" });
+        self.close();
+        Ok(())
+    }
+}
+
+// Any two players, usually built-in AIs and bots, without a terminal
+pub fn play_match(first: &mut Player, second: &mut Player, rules: &Rules, seed: u64) -> MatchResult {
+    let mut game = new_game(rules.width, rules.height, rules.adjacency, &rules.fleet, rules.mode, Opponent::BOT, seed);
+    let outcome = match setup_game(&mut game, first, second) {
+        Ok(()) => play_game(&mut game, first, second),
+        Err(e) => Err(e)
+    };
+    let (winner, forfeit) = match outcome {
+        Ok(winner) => (winner, None),
+        Err((loser, Failure::ERROR(reason))) => (other_turn(loser), Some(reason)),
+        Err((loser, Failure::QUIT)) => (other_turn(loser), Some("left the game".to_string()))
+    };
+    let _ = finish_game(first, second, winner);
+    MatchResult {
+        winner : winner,
+        forfeit : forfeit,
+        game : game
+    }
+}
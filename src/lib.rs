@@ -0,0 +1,18 @@
+//! Battleship game engine: fields, ship placement, shot resolution and the AI.
+//! It doesn't depend on any terminal library, so it can be embedded and tested
+//! without a TTY. The ncurses front end is the `battleship` binary.
+
+extern crate rand;
+
+pub mod field;
+pub mod fleet;
+pub mod game;
+pub mod rng;
+pub mod ai;
+pub mod save;
+pub mod replay;
+pub mod sha256;
+pub mod net;
+pub mod player;
+pub mod bot;
+pub mod headless;
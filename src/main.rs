@@ -1,21 +1,22 @@
-#![feature(globs)]
-#![feature(negate_unsigned)]
-
 extern crate ncurses;
-extern crate rand;
+extern crate battleship;
 
-use ncurses::*;
-use rand::Rng;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
 
-#[derive(Copy, Clone)]
-enum CellType {
-    EMPTY = 0,
-    SHOT = 1,
-    SHIP = 2,
-    COLLISION_SHIP = 3,
-    HIDE_SHIP = 4,
-    SHOT_POS = 5,
-}
+use ncurses::*;
+use battleship::field::*;
+use battleship::fleet::*;
+use battleship::game::*;
+use battleship::ai::*;
+use battleship::save::*;
+use battleship::replay::*;
+use battleship::rng::*;
+use battleship::net::*;
+use battleship::bot::*;
+use battleship::headless::*;
+use battleship::player::*;
 
 #[derive(Copy, Clone)]
 enum Color {
@@ -31,9 +32,16 @@ enum Color {
 enum Status {
     START = 0,
     PLACE_SHIP = 1,
-    PLAYER_TURN = 2,
-    AI_TURN = 3,
-    QUIT = 4,
+    // Both players take turns, see `player`
+    PLAY = 2,
+    QUIT = 3,
+    GAME_OVER = 4,
+}
+
+// Side of a network game, given on the command line
+enum NetworkRole {
+    HOST(u16),
+    CONNECT(String),
 }
 
 static COLOR_PAIR_NUMBER: i16 = 1;
@@ -45,77 +53,40 @@ static COLOR_PAIR_SHOT_POS: i16 = 6;
 
 static DEBUG: bool = false;
 
-#[derive(Copy, Clone)]
-struct Cell {
-    Type: CellType,
-    Color: Color
-}
-
-#[derive(Copy, Clone)]
-struct Coord {
-    y: u32,
-    x: u32
-}
-
-struct ShipCell {
-    coord: Coord,
-    cell: CellType
-}
-
-struct Ship {
-    coord: Coord,
-    cells: Vec<ShipCell>,
-    can_be_placed: bool
-}
-
-type Field = [[Cell; 10]; 10];
-
-impl Cell {
-   fn print(&self) {
-       let mut symbol = " ";
-       match self.Type {
-           CellType::HIDE_SHIP => {
-               if DEBUG {
-               attron(COLOR_PAIR(COLOR_PAIR_SHIP));
-               printw(".");
-               attroff(COLOR_PAIR(COLOR_PAIR_SHIP));
-               }
-               else {
-               attron(COLOR_PAIR(COLOR_PAIR_EMPTY));
-               printw(".");
-               attroff(COLOR_PAIR(COLOR_PAIR_EMPTY));
-               }
-           }
-           CellType::EMPTY => {
-               attron(COLOR_PAIR(COLOR_PAIR_EMPTY));
-               printw(".");
-               attroff(COLOR_PAIR(COLOR_PAIR_EMPTY));
-           }
-           CellType::SHOT => {
-               attron(COLOR_PAIR(COLOR_PAIR_SHOT));
-               printw("o");
-               attroff(COLOR_PAIR(COLOR_PAIR_SHOT));
-           }
-           CellType::SHIP => {
-               attron(COLOR_PAIR(COLOR_PAIR_SHIP));
-               printw("x");
-               attroff(COLOR_PAIR(COLOR_PAIR_SHIP));
-           }
-           CellType::COLLISION_SHIP => {
-               attron(COLOR_PAIR(COLOR_PAIR_COLLISION_SHIP));
-               printw("x");
-               attroff(COLOR_PAIR(COLOR_PAIR_COLLISION_SHIP));
-           }
-           CellType::SHOT_POS => {
-               attron(COLOR_PAIR(COLOR_PAIR_SHOT_POS));
-               printw("o");
-               attroff(COLOR_PAIR(COLOR_PAIR_SHOT_POS));
-           }
-       }
-   }
+fn print_cell(cell: &Cell, highlight: bool, marked: bool) {
+    let mut symbol = " ";
+    let mut pair = COLOR_PAIR_EMPTY;
+    match cell.Type {
+        CellType::EMPTY => {
+            symbol = ".";
+        }
+        CellType::SHOT => {
+            symbol = "o";
+            pair = COLOR_PAIR_SHOT;
+        }
+        CellType::SHIP => {
+            symbol = "x";
+            pair = COLOR_PAIR_SHIP;
+        }
+        CellType::COLLISION_SHIP => {
+            symbol = "x";
+            pair = COLOR_PAIR_COLLISION_SHIP;
+        }
+    }
+    // Cells marked for a salvo are not shot yet
+    if marked {
+        symbol = "*";
+        pair = COLOR_PAIR_SHOT;
+    }
+    // Aiming cursor is drawn over the cell, so it's still visible what is under it
+    if highlight {
+        pair = COLOR_PAIR_SHOT_POS;
+    }
+    attron(COLOR_PAIR(pair));
+    printw(symbol);
+    attroff(COLOR_PAIR(pair));
 }
 
-
 fn init_colors() {
     start_color();
 
@@ -130,7 +101,7 @@ fn init_colors() {
     init_pair(COLOR_PAIR_EMPTY, Color::WHITE as i16, Color::BACKGROUND as i16);
     init_pair(COLOR_PAIR_SHIP, Color::GREEN as i16, Color::BACKGROUND as i16);
     init_pair(COLOR_PAIR_SHOT, Color::PINK as i16, Color::BACKGROUND as i16);
-    init_pair(COLOR_PAIR_SHOT_POS, Color::BLUE as i16, Color::BACKGROUND as i16);
+    init_pair(COLOR_PAIR_SHOT_POS, Color::BACKGROUND as i16, Color::BLUE as i16);
     init_pair(COLOR_PAIR_COLLISION_SHIP, Color::RED as i16, Color::BACKGROUND as i16);
 
     bkgd(' ' as u64 | COLOR_PAIR(COLOR_PAIR_EMPTY) as u64);
@@ -155,118 +126,84 @@ fn get_input() -> i32 {
     getch()
 }
 
-fn print_field(field: Field, y: i32, x: i32) {
+fn print_field(field: &Field, y: i32, x: i32, cursor: Option<Coord>, marks: &Vec<Coord>) {
+    // Numbers are right-aligned, so two-digit ones stay over their column
+    attron(COLOR_PAIR(COLOR_PAIR_NUMBER));
     mv(y, x+2i32);
-    for i in 1..11 {
-        attron(COLOR_PAIR(COLOR_PAIR_NUMBER));
-        println!(" {}", i);
-        attroff(COLOR_PAIR(COLOR_PAIR_NUMBER));
+    for j in 0..field.width {
+        printw(&format!("{:>2}", j+1));
     }
-    for i in 1..11 {
-        if i != 10 {
-            mv(y+(i as i32), x+1i32);
-        }
-        else {
-            mv(y+(i as i32), x);
-        }
-        attron(COLOR_PAIR(COLOR_PAIR_NUMBER));
-        println!("{}", i);
-        attroff(COLOR_PAIR(COLOR_PAIR_NUMBER));
+    for i in 0..field.height {
+        mv(y+(i as i32)+1i32, x);
+        printw(&format!("{:>2}", i+1));
     }
-    for i in 0..10 {
+    attroff(COLOR_PAIR(COLOR_PAIR_NUMBER));
+    for i in 0..field.height {
         mv(y+(i as i32)+1i32, x+2i32);
-        for j in 0..10 {
+        for j in 0..field.width {
             printw(" ");
-            field[i][j].print();
+            let highlight = match cursor {
+                Some(pos) => pos.y as usize == i && pos.x as usize == j,
+                None => false
+            };
+            let marked = marks.contains(&Coord { y : i as u32, x : j as u32 });
+            match field.cell(Pos { y : i as i32, x : j as i32 }) {
+                Some(cell) => { print_cell(cell, highlight, marked); }
+                None => {}
+            }
         }
-        println!("");
     }
 }
 
-fn add_ship(field: &mut Field, ship: &Ship) {
-    let y = ship.coord.y as usize;
-    let x = ship.coord.x as usize;
-    for i in ship.cells.iter() {
-        let cy = i.coord.y as usize;
-        let cx = i.coord.x as usize;
-        field[y+cy][x+cx] = Cell { Type : i.cell, Color : Color::GREEN };
-    }
-}
-
-fn remove_ship(field: &mut Field, ship: &Ship) {
-    let y = ship.coord.y as usize;
-    let x = ship.coord.x as usize;
-    for i in ship.cells.iter() {
-        let cy = i.coord.y as usize;
-        let cx = i.coord.x as usize;
-        field[y+cy][x+cx] = Cell { Type : CellType::EMPTY, Color : Color::WHITE };
-    }
+fn print_fields(userfield: &Field, enemyfield: &Field, cursor: Option<Coord>, marks: &Vec<Coord>) {
+    let mut height : i32 = 0;
+    let mut width : i32 = 0;
+    getmaxyx(stdscr, &mut height, &mut width);
+    let y = height/2 - userfield.height as i32/2;
+    print_field(userfield, y, width/2-8-field_screen_width(userfield), None, &vec![]);
+    print_field(enemyfield, y, width/2+5, cursor, marks);
 }
 
-fn rotate_ship(ship: &mut Ship) {
-    for i in 0..ship.cells.len() {
-        let temp = ship.cells[i].coord.y;
-        ship.cells[i].coord.y = ship.cells[i].coord.x;
-        ship.cells[i].coord.x = temp;
-    }
+fn print_message(field: &Field, message: &str) {
+    let mut height : i32 = 0;
+    let mut width : i32 = 0;
+    getmaxyx(stdscr, &mut height, &mut width);
+    mv(height/2 + field.height as i32/2 + 2, width/2-8-field_screen_width(field));
+    printw(message);
 }
 
-fn have_neighbours(field: Field, y: usize, x: usize) -> bool {
-    let mx : i32 = x as i32+1;
-    let lx : i32 = x as i32-1;
-    let my : i32 = y as i32+1;
-    let ly : i32 = y as i32-1;
-
-    let mxx : usize = x as usize - 1;
-    let lxx : usize = x as usize + 1;
-    let myy : usize = y as usize - 1;
-    let lyy : usize = y as usize + 1;
-
-    if field[y as usize][x as usize].Type as i32 == CellType::SHIP as i32 { return true; }
-    if (lx >= 0) && (field[y as usize][mxx].Type as i32 == CellType::SHIP as i32) { return true; }
-    if (mx < 10) && (field[y as usize][lxx].Type as i32 == CellType::SHIP as i32) { return true; }
-    if (ly >= 0) && (field[myy][x as usize].Type as i32 == CellType::SHIP as i32) { return true; }
-    if (my < 10) && (field[lyy][x as usize].Type as i32 == CellType::SHIP as i32) { return true; }
-    if (ly >= 0) && (lx >= 0) && (field[myy][mxx].Type as i32 == CellType::SHIP as i32)  { return true; }
-    if (ly >= 0) && (mx < 10) && (field[myy][lxx].Type as i32 == CellType::SHIP as i32)  { return true; }
-    if (my < 10) && (lx >= 0) && (field[lyy][mxx].Type as i32 == CellType::SHIP as i32)  { return true; }
-    if (my < 10) && (mx < 10) && (field[lyy][lxx].Type as i32 == CellType::SHIP as i32)  { return true; }
-
-    return false;
+// Line under the message
+fn print_status(field: &Field, status: &str) {
+    let mut height : i32 = 0;
+    let mut width : i32 = 0;
+    getmaxyx(stdscr, &mut height, &mut width);
+    mv(height/2 + field.height as i32/2 + 3, width/2-8-field_screen_width(field));
+    printw(status);
 }
 
-fn collision(field: &Field, ship: &mut Ship) -> bool {
-    let y = ship.coord.y as usize;
-    let x = ship.coord.x as usize;
-    println!("{} {}", y, x);
-    let mut canbeplaced = true;
-    for i in 0..ship.cells.len() {
-        let cy = ship.cells[i].coord.y as usize;
-        let cx = ship.cells[i].coord.x as usize;
-        println!("{} {}", cy, cx);
-        if (y+cy == -1) || (y+cy == 10) || (x+cx == -1) || (x+cx == 10) {
-            return true;
-        }
-
-        // Check not only y+cy;x+cx, but and around this cell
-        if have_neighbours(*field, y+cy, x+cx) {
-            ship.can_be_placed = false;
-            canbeplaced = false;
-            ship.cells[i].cell = CellType::COLLISION_SHIP;
-        }
-        else if field[y+cy][x+cx].Type as i32 == CellType::EMPTY as i32 {
-            ship.cells[i].cell = CellType::SHIP;
+fn salvo_summary(results: &Vec<ShotResult>) -> String {
+    let mut hits = 0;
+    let mut sunk = 0;
+    let mut misses = 0;
+    for result in results.iter() {
+        match *result {
+            ShotResult::MISS => { misses += 1; }
+            ShotResult::HIT => { hits += 1; }
+            ShotResult::SUNK(_) => { hits += 1; sunk += 1; }
+            _ => {}
         }
     }
+    format!("{} hit(s), {} sunk, {} miss(es).", hits, sunk, misses)
+}
 
-    if (canbeplaced) {
-        ship.can_be_placed = true;
-    }
-
-    return false;
+// Row numbers plus two characters per cell
+fn field_screen_width(field: &Field) -> i32 {
+    2 + 2*field.width as i32
 }
 
-fn move_ship(field: &mut Field, ship: &mut Ship, input: i32) -> bool {
+
+// A move that would leave the field isn't made, `refused` says why
+fn move_ship(field: &mut Field, ship: &mut Ship, input: i32, refused: &mut Vec<Violation>) -> bool {
     let mut x = ship.coord.x;
     let mut y = ship.coord.y;
 
@@ -274,24 +211,28 @@ fn move_ship(field: &mut Field, ship: &mut Ship, input: i32) -> bool {
         KEY_LEFT =>  {
             ship.coord.x-=1;
             if collision(&*field, ship) {
+                *refused = ship_violations(&*field, ship);
                 ship.coord.x+=1;
             }
         }
         KEY_UP =>  {
             ship.coord.y-=1;
             if collision(&*field, ship) {
+                *refused = ship_violations(&*field, ship);
                 ship.coord.y+=1;
             }
         }
         KEY_DOWN =>  {
             ship.coord.y+=1;
             if collision(&*field, ship) {
+                *refused = ship_violations(&*field, ship);
                 ship.coord.y-=1;
             }
         }
         KEY_RIGHT =>  {
             ship.coord.x+=1;
             if collision(&*field, ship) {
+                *refused = ship_violations(&*field, ship);
                 ship.coord.x-=1;
             }
         }
@@ -300,34 +241,25 @@ fn move_ship(field: &mut Field, ship: &mut Ship, input: i32) -> bool {
                 return true;
             }
         }
+        m if m == 'm' as i32 => {
+            mirror_ship(ship);
+            if collision(&*field, ship) {
+                *refused = ship_violations(&*field, ship);
+                mirror_ship(ship);
+            }
+        }
         _ => {
             rotate_ship(ship);
             if collision(&*field, ship) {
-                rotate_ship(ship);
+                *refused = ship_violations(&*field, ship);
+                rotate_ship_back(ship);
             }
         }
     }
     return false;
 }
 
-fn remember_before(field: Field, ship: &Ship, before: &mut Vec<ShipCell>) {
-    before.clear();
-    let cury = ship.coord.y as usize;
-    let curx = ship.coord.x as usize;
-    for i in ship.cells.iter() {
-        let y = cury + i.coord.y as usize;
-        let x = curx + i.coord.x as usize;
-        before.push(ShipCell { coord : Coord { y: y as u32, x: x as u32}, cell : field[y][x].Type });
-    }
-}
-
-fn place_before(field: &mut Field, before: &Vec<ShipCell>) {
-    for i in before.iter() {
-        field[i.coord.y as usize][i.coord.x as usize].Type = i.cell;
-    }
-}
-
-fn print_menu() {
+fn print_menu(difficulty: Difficulty, field_width: usize, field_height: usize, fleet: &Fleet, adjacency: Adjacency, mode: GameMode, opponent: &str, has_save: bool, has_replay: bool, message: &str) {
     clear();
     let mut height : i32 = 0;
     let mut width : i32 = 0;
@@ -344,12 +276,45 @@ fn print_menu() {
     printw("| |_/ / (_| | |_| |_| |  __/\\__ \\ | | | | |_) |\n");
     mv(height/2-3, width/2-23);
     printw("\\____/ \\__,_|\\__|\\__|_|\\___||___/_| |_|_| .__/ \n");
+    if has_save {
+        mv(height/2+2, width/2-7);
+        printw("<C>: Continue\n");
+    }
     mv(height/2+3, width/2-8);
     printw("<F1>: Start game\n");
-    mv(height/2+4, width/2-5);
+    mv(height/2+4, width/2-10);
+    match difficulty {
+        Difficulty::EASY => { printw("<D>: Difficulty: Easy\n"); }
+        Difficulty::HARD => { printw("<D>: Difficulty: Hard\n"); }
+    }
+    mv(height/2+5, width/2-12);
+    printw(&format!("<W>/<H>: Field size: {}x{}\n", field_width, field_height));
+    mv(height/2+6, width/2-10);
+    printw(&format!("<F>: Fleet: {}\n", fleet.name));
+    mv(height/2+7, width/2-12);
+    match adjacency {
+        Adjacency::NO_TOUCHING => { printw("<A>: Ships: No touching\n"); }
+        Adjacency::CORNERS_ALLOWED => { printw("<A>: Ships: Corners may touch\n"); }
+        Adjacency::TOUCHING_ALLOWED => { printw("<A>: Ships: May touch\n"); }
+    }
+    mv(height/2+8, width/2-10);
+    match mode {
+        GameMode::CLASSIC => { printw("<M>: Mode: Classic\n"); }
+        GameMode::SALVO => { printw("<M>: Mode: Salvo\n"); }
+        GameMode::FIXED_SALVO(shots) => { printw(&format!("<M>: Mode: Salvo, {} shots\n", shots)); }
+    }
+    mv(height/2+9, width/2-12);
+    printw(&format!("<O>: Opponent: {}\n", opponent));
+    mv(height/2+10, width/2-5);
     printw("<F2>: About\n");
-    mv(height/2+5, width/2-5);
+    mv(height/2+11, width/2-5);
     printw("<Q>: Quit\n");
+    if has_replay {
+        mv(height/2+12, width/2-11);
+        printw("<R>: Replay last game\n");
+    }
+    mv(height/2+14, width/2-(message.len() as i32)/2);
+    printw(message);
 }
 
 fn print_about() {
@@ -363,115 +328,728 @@ fn print_about() {
     printw("Sources: https://github.com/queyenth/battleship\n");
 }
 
-fn ai_place_ship(field : &mut Field) {
-    let mut curShip : Ship = Ship {
-        coord : Coord {y : 0, x : 1},
-        can_be_placed: true,
-        cells : vec![ ShipCell { coord : Coord {y : -1, x : 0}, cell : CellType::SHIP },
-                      ShipCell { coord : Coord {y : 0, x : 0}, cell : CellType::SHIP},
-                      ShipCell { coord : Coord {y : 1, x : 0}, cell : CellType::SHIP},
-                      ShipCell { coord : Coord {y : 2, x : 0}, cell : CellType::SHIP}]
-    };
-    let mut count : u32 = 0;
-    while count != 10 {
-        let x = (rand::thread_rng().gen_range(0, 10));
-        let y = (rand::thread_rng().gen_range(0, 10));
-        let r = (rand::thread_rng().gen_range(0, 2));
-        if count == 0 {
-            curShip = Ship {
-                coord : Coord {y : y, x : x},
-                can_be_placed: true,
-                cells : vec![ ShipCell { coord : Coord {y : -1, x : 0}, cell : CellType::SHIP },
-                              ShipCell { coord : Coord {y : 0, x : 0}, cell : CellType::SHIP},
-                              ShipCell { coord : Coord {y : 1, x : 0}, cell : CellType::SHIP},
-                              ShipCell { coord : Coord {y : 2, x : 0}, cell : CellType::SHIP}]
-            };
-        }
-        else if count <= 2 {
-            curShip = Ship {
-                coord : Coord {y : y, x : x},
-                can_be_placed: true,
-                cells : vec![ ShipCell { coord : Coord {y : -1, x : 0}, cell : CellType::SHIP},
-                              ShipCell { coord : Coord {y : 0, x : 0}, cell : CellType::SHIP},
-                              ShipCell { coord : Coord {y : 1, x : 0}, cell : CellType::SHIP}]
-            };
+fn print_game_over(title: &str, shots_fired: u32, shots_hit: u32, seed: u64, has_replay: bool) {
+    clear();
+    let mut height : i32 = 0;
+    let mut width : i32 = 0;
+    getmaxyx(stdscr, &mut height, &mut width);
+    mv(height/2-4, width/2-(title.len() as i32)/2);
+    printw(title);
+    let accuracy = if shots_fired == 0 { 0 } else { shots_hit * 100 / shots_fired };
+    mv(height/2-2, width/2-8);
+    printw(&format!("Shots fired: {}\n", shots_fired));
+    mv(height/2-1, width/2-8);
+    printw(&format!("Accuracy: {}%\n", accuracy));
+    mv(height/2, width/2-8);
+    printw(&format!("Seed: {}\n", seed));
+    mv(height/2+2, width/2-8);
+    printw("<F1>: Play again\n");
+    mv(height/2+3, width/2-8);
+    printw("<F2>: Main menu\n");
+    let mut line = height/2+4;
+    if has_replay {
+        mv(line, width/2-8);
+        printw("<R>: Watch replay\n");
+        line += 1;
+    }
+    mv(line, width/2-8);
+    printw("<Q>: Quit\n");
+}
+
+fn print_replay(replay: &Replay, step: usize, playing: bool, delay: i32) {
+    clear();
+    let (userfield, enemyfield) = replay_step(replay, step);
+    let mut height : i32 = 0;
+    let mut width : i32 = 0;
+    getmaxyx(stdscr, &mut height, &mut width);
+    let y = height/2 - userfield.height as i32/2;
+    let x = width/2-8-field_screen_width(&userfield);
+
+    // The last shot is highlighted on the field it was fired at
+    let mut user_cursor : Option<Coord> = None;
+    let mut enemy_cursor : Option<Coord> = None;
+    let mut message = format!("Start of the game, {} shots recorded.", replay.shots.len());
+    if step > 0 {
+        let shot = &replay.shots[step-1];
+        let shooter = match shot.turn {
+            Turn::PLAYER => { enemy_cursor = Some(shot.coord); "Player" }
+            Turn::AI => { user_cursor = Some(shot.coord); "AI" }
+        };
+        message = format!("Shot {}/{}: {} fires at row {}, column {}: {}",
+                          step, replay.shots.len(), shooter, shot.coord.y+1, shot.coord.x+1, outcome_name(&shot.result));
+    }
+    print_field(&userfield, y, x, user_cursor, &vec![]);
+    print_field(&enemyfield, y, width/2+5, enemy_cursor, &vec![]);
+
+    mv(y + userfield.height as i32 + 2, x);
+    printw(&message);
+    mv(y + userfield.height as i32 + 3, x);
+    if playing {
+        printw(&format!("Playing, {} ms per shot. <Space>: Pause  <+>/<->: Speed  <Q>: Back", delay));
+    }
+    else {
+        printw("<Left>/<Right>: Step  <Home>/<End>: Jump  <Space>: Play  <Q>: Back");
+    }
+}
+
+fn watch_replay(replay: &Replay) {
+    let mut step = 0;
+    let mut playing = false;
+    let mut delay = 500;
+    loop {
+        print_replay(replay, step, playing, delay);
+        // While playing getch gives up after the delay and the replay moves on
+        timeout(if playing { delay } else { -1 });
+        let ch = getch();
+        match ch {
+            KEY_RIGHT => {
+                if step < replay.shots.len() {
+                    step+=1;
+                }
+            }
+            KEY_LEFT => {
+                if step > 0 {
+                    step-=1;
+                }
+            }
+            KEY_HOME => { step = 0; }
+            KEY_END => { step = replay.shots.len(); }
+            space if space == ' ' as i32 => {
+                playing = !playing;
+                if playing && step == replay.shots.len() {
+                    step = 0;
+                }
+            }
+            plus if plus == '+' as i32 => {
+                if delay > 50 {
+                    delay /= 2;
+                }
+            }
+            minus if minus == '-' as i32 => {
+                if delay < 4000 {
+                    delay *= 2;
+                }
+            }
+            qkey if qkey == 'q' as i32 => { break; }
+            ERR => {
+                if step < replay.shots.len() {
+                    step+=1;
+                }
+                else {
+                    playing = false;
+                }
+            }
+            _ => {}
         }
-        else if count <= 5 {
-            curShip = Ship {
-                coord : Coord {y : y, x : x},
-                can_be_placed: true,
-                cells : vec![ ShipCell { coord : Coord {y : 0, x : 0}, cell : CellType::SHIP},
-                              ShipCell { coord : Coord {y : 1, x : 0}, cell : CellType::SHIP}]
-            };
+    }
+    timeout(-1);
+}
+
+fn opponent_label(opponent: Opponent, network: &Option<NetworkRole>) -> String {
+    match (opponent, network) {
+        (Opponent::COMPUTER, _) => "Computer".to_string(),
+        (Opponent::HUMAN, _) => "Hot seat".to_string(),
+        (Opponent::REMOTE, &Some(NetworkRole::HOST(port))) => format!("Network, host on port {}", port),
+        (Opponent::REMOTE, &Some(NetworkRole::CONNECT(ref address))) => format!("Network, join {}", address),
+        (Opponent::REMOTE, &None) => "Network".to_string(),
+        (Opponent::BOT, _) => "Bots".to_string()
+    }
+}
+
+fn print_waiting(text: &str) {
+    clear();
+    let mut height : i32 = 0;
+    let mut width : i32 = 0;
+    getmaxyx(stdscr, &mut height, &mut width);
+    mv(height/2, width/2-(text.len() as i32)/2);
+    printw(text);
+    refresh();
+}
+
+// Sets up a network game, the guest gets the rules from the host
+fn start_network_game(role: &NetworkRole, game: &mut Game) -> Result<Connection, String> {
+    match *role {
+        NetworkRole::HOST(port) => {
+            print_waiting(&format!("Waiting for a player on port {}...", port));
+            let mut connection = try!(host(port).map_err(|e| e.to_string()));
+            try!(host_handshake(&mut connection, &game_rules(game)));
+            Ok(connection)
         }
-        else if count <= 9 {
-            curShip = Ship {
-                coord : Coord {y : y, x : x},
-                can_be_placed: true,
-                cells : vec![ ShipCell { coord : Coord {y : 0, x : 0}, cell : CellType::SHIP}]
-            };
+        NetworkRole::CONNECT(ref address) => {
+            print_waiting(&format!("Connecting to {}...", address));
+            let mut connection = try!(connect(address).map_err(|e| e.to_string()));
+            let rules = try!(guest_handshake(&mut connection));
+            let seed = game.rng.seed;
+            *game = new_game(rules.width, rules.height, rules.adjacency, &rules.fleet, rules.mode, Opponent::REMOTE, seed);
+            Ok(connection)
         }
-        if r == 1 {
-            rotate_ship(&mut curShip);
+    }
+}
+
+fn player_name(opponent: Opponent, turn: Turn) -> &'static str {
+    match (opponent, turn) {
+        (Opponent::COMPUTER, Turn::PLAYER) => "You",
+        (Opponent::COMPUTER, Turn::AI) => "Computer",
+        (Opponent::HUMAN, Turn::PLAYER) => "Player 1",
+        (Opponent::HUMAN, Turn::AI) => "Player 2",
+        (Opponent::REMOTE, Turn::PLAYER) => "You",
+        (Opponent::REMOTE, Turn::AI) => "Opponent",
+        (Opponent::BOT, Turn::PLAYER) => "Player 1",
+        (Opponent::BOT, Turn::AI) => "Player 2"
+    }
+}
+
+// Nobody sees a fleet but its owner, so this waits with both fields hidden
+fn pass_keyboard(name: &str) {
+    let mut height : i32 = 0;
+    let mut width : i32 = 0;
+    loop {
+        clear();
+        getmaxyx(stdscr, &mut height, &mut width);
+        let text = format!("Pass the keyboard to {}", name);
+        mv(height/2-1, width/2-(text.len() as i32)/2);
+        printw(&text);
+        mv(height/2+1, width/2-13);
+        printw("<Space>: I'm ready, show it");
+        if getch() == ' ' as i32 {
+            break;
         }
-        if collision(field, &mut curShip) {
-            continue;
+    }
+}
+
+fn place_message(kind: &ShipType) -> String {
+    if is_straight(&kind.shape) {
+        format!("Place your {} ({}-deck)", kind.name, kind.length())
+    } else {
+        format!("Place your {} ({}-deck), M mirrors it", kind.name, kind.length())
+    }
+}
+
+// Draws the ship onto the field, the status says what's wrong with its place
+fn show_ship(own: &mut Field, ship: &mut Ship, fleet: &Fleet) -> String {
+    collision(own, ship);
+    let status = violations_message(&validate_placement(own, ship, fleet));
+    add_ship(own, ship);
+    return status;
+}
+
+// Keyboard placement of the whole fleet, false if the player quits.
+// The other board is drawn empty, nothing is known of it yet
+fn place_fleet(own: &mut Field, fleet: &Fleet) -> bool {
+    let other = empty_field(own.width, own.height, own.adjacency);
+    let ships = fleet_ships(fleet);
+    let mut curShip : Ship = shaped_ship(&ships[0].shape, Coord {y : 0, x : 0});
+
+    let mut before : Vec<ShipCell> = vec![];
+
+    remember_before(own, &curShip, &mut before);
+    let mut status = show_ship(own, &mut curShip, fleet);
+
+    clear();
+    print_fields(own, &other, None, &vec![]);
+    print_message(own, &place_message(&ships[0]));
+    print_status(own, &status);
+
+    let mut count = 0;
+    let mut ch = getch();
+
+    loop {
+        if ch == ('q' as i32) {
+            return false;
         }
-        else if curShip.can_be_placed {
+        remove_ship(own, &curShip);
+        let mut refused : Vec<Violation> = vec![];
+        let shipWasPlaced = move_ship(own, &mut curShip, ch, &mut refused);
+        place_before(own, &before);
+        remember_before(own, &curShip, &mut before);
+        if (shipWasPlaced) {
+            place_ship(own, &curShip, &ships[count].name);
             count+=1;
-            add_ship(field, &curShip);
+            if count < ships.len() {
+                curShip = shaped_ship(&ships[count].shape, Coord {y : 0, x : 0});
+                remember_before(own, &curShip, &mut before);
+                status = show_ship(own, &mut curShip, fleet);
+            }
+            else {
+                return true;
+            }
+        }
+        else {
+            // Checked again, cells of other ships under it were cleared while it moved
+            status = show_ship(own, &mut curShip, fleet);
         }
+        if refused.len() != 0 {
+            status = violations_message(&refused);
+        }
+
+        clear();
+        print_fields(own, &other, None, &vec![]);
+        print_message(own, &place_message(&ships[count]));
+        print_status(own, &status);
+
+        // Need refresh after each frame?
+        refresh();
+        ch = getch();
+    }
+}
+
+fn shot_message(name: &str, result: &ShotResult) -> String {
+    match *result {
+        ShotResult::MISS => format!("{}: Miss.", name),
+        ShotResult::HIT => "Hit!".to_string(),
+        ShotResult::SUNK(ref cells) => format!("{}-deck ship is sunk!", cells.len()),
+        ShotResult::ALREADY_SHOT => "You already shot there.".to_string(),
+        ShotResult::OUT_OF_BOUNDS => String::new()
     }
 }
 
-fn hide_ships(field: &mut Field) {
-    for i in 0..10 {
-        for j in 0..10 {
-            if field[i][j].Type as i32 == CellType::SHIP as i32 {
-                field[i][j].Type = CellType::HIDE_SHIP;
+// Player at the keyboard. In hot seat both sides are humans, and nobody sees
+// a fleet before the keyboard is passed
+struct HumanPlayer {
+    name: String,
+    opponent: String,
+    hot_seat: bool,
+    // Keyboard has to change hands before the next shot
+    pass: bool,
+    cursor: Coord,
+    // Results since the player last chose a shot
+    fired: Vec<ShotResult>,
+    received: Vec<ShotResult>,
+    // Names of own ships sunk since then
+    lost: Vec<String>
+}
+
+fn human_player(name: &str, opponent: &str, hot_seat: bool) -> HumanPlayer {
+    HumanPlayer {
+        name : name.to_string(),
+        opponent : opponent.to_string(),
+        hot_seat : hot_seat,
+        pass : hot_seat,
+        cursor : Coord { y : 0, x : 0 },
+        fired : vec![],
+        received : vec![],
+        lost : vec![]
+    }
+}
+
+impl HumanPlayer {
+    // What happened since the player last chose a shot
+    fn news(&self) -> String {
+        let mut parts : Vec<String> = vec![];
+        match self.fired.len() {
+            0 => {}
+            1 => { parts.push(shot_message(&self.name, &self.fired[0])); }
+            _ => { parts.push(format!("{} fired a salvo: {}", self.name, salvo_summary(&self.fired))); }
+        }
+        if self.received.len() > 0 {
+            parts.push(format!("{}: {}", self.opponent, salvo_summary(&self.received)));
+        }
+        if self.lost.len() > 0 {
+            parts.push(format!("Lost: {}.", self.lost.join(", ")));
+        }
+        parts.join(" ")
+    }
+
+    fn draw(&self, own: &Field, target: &OpponentView, cursor: Option<Coord>, marks: &Vec<Coord>, message: &str) {
+        clear();
+        print_fields(own, &known_field(target), cursor, marks);
+        print_message(own, message);
+        refresh();
+    }
+
+    // The cursor stays on the field
+    fn move_cursor(&mut self, target: &OpponentView, dy: i32, dx: i32) {
+        match target.coord(self.cursor.pos().offset(dy, dx)) {
+            Some(coord) => { self.cursor = coord; }
+            None => {}
+        }
+    }
+}
+
+impl Player for HumanPlayer {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn place_fleet(&mut self, own: &mut Field, fleet: &Fleet, rng: &mut GameRng) -> Result<(), Failure> {
+        if DEBUG {
+            ai_place_ship(own, fleet, rng);
+            return Ok(());
+        }
+        if self.hot_seat {
+            pass_keyboard(&self.name);
+        }
+        if place_fleet(own, fleet) { Ok(()) } else { Err(Failure::QUIT) }
+    }
+
+    fn choose_shots(&mut self, own: &Field, target: &OpponentView, shots: usize, _rng: &mut GameRng) -> Result<Vec<Coord>, Failure> {
+        if self.pass {
+            pass_keyboard(&self.name);
+            self.pass = false;
+        }
+        let mut message = self.news();
+        self.fired.clear();
+        self.received.clear();
+        self.lost.clear();
+        let mut salvo : Vec<Coord> = vec![];
+        loop {
+            self.draw(own, target, Some(self.cursor), &salvo, &message);
+            let ch = getch();
+            match ch {
+                KEY_LEFT => { self.move_cursor(target, 0, -1); }
+                KEY_RIGHT => { self.move_cursor(target, 0, 1); }
+                KEY_UP => { self.move_cursor(target, -1, 0); }
+                KEY_DOWN => { self.move_cursor(target, 1, 0); }
+                KEY_F1 => {
+                    // A single shot needs no marks
+                    if shots == 1 && salvo.len() == 0 {
+                        if target.knowledge(self.cursor.pos()) == Some(Knowledge::UNKNOWN) {
+                            return Ok(vec![self.cursor]);
+                        }
+                        message = shot_message(&self.name, &ShotResult::ALREADY_SHOT);
+                    }
+                    else if salvo.len() < shots {
+                        message = format!("Mark {} more cell(s) with <Space>.", shots - salvo.len());
+                    }
+                    else {
+                        return Ok(salvo);
+                    }
+                }
+                space if space == ' ' as i32 => {
+                    match salvo.iter().position(|&pos| pos == self.cursor) {
+                        Some(index) => { salvo.remove(index); }
+                        None => {
+                            let unshot = target.knowledge(self.cursor.pos()) == Some(Knowledge::UNKNOWN);
+                            if unshot && shots > 1 && salvo.len() < shots {
+                                salvo.push(self.cursor);
+                            }
+                        }
+                    }
+                }
+                qkey if qkey == 'q' as i32 => {
+                    return Err(Failure::QUIT);
+                }
+                _ => {}
             }
         }
     }
+
+    fn observe(&mut self, own: &Field, target: &OpponentView, _coord: Coord, result: &ShotResult) -> Result<(), Failure> {
+        self.fired.push(result.clone());
+        self.draw(own, target, None, &vec![], &self.news());
+        Ok(())
+    }
+
+    // Before the keyboard changes hands the shooter sees how the turn went
+    fn end_turn(&mut self, own: &Field, target: &OpponentView) -> Result<(), Failure> {
+        if self.hot_seat {
+            let message = format!("{} Press any key to pass the keyboard.", self.news());
+            self.draw(own, target, None, &vec![], &message);
+            getch();
+            self.fired.clear();
+        }
+        Ok(())
+    }
+
+    fn incoming(&mut self, own: &Field, target: &OpponentView, coord: Coord, result: &ShotResult) -> Result<(), Failure> {
+        self.received.push(result.clone());
+        match *result {
+            ShotResult::SUNK(_) => match ship_at(own, coord.y as usize, coord.x as usize) {
+                Some(ship) => { self.lost.push(ship.name.clone()); }
+                None => {}
+            },
+            _ => {}
+        }
+        if self.hot_seat {
+            // The other player is at the keyboard
+            self.pass = true;
+        }
+        else {
+            self.draw(own, target, None, &vec![], &self.news());
+        }
+        Ok(())
+    }
 }
 
-fn tryToShot(field: &mut Field, y: u32, x: u32) -> bool {
-    if field[y as usize][x as usize].Type as i32 == CellType::HIDE_SHIP as i32 {
-        field[y as usize][x as usize].Type = CellType::COLLISION_SHIP;
-        return true;
+// Sides of a new or loaded game, the first one has the user's field. The
+// computer goes on with the state of a loaded game, or starts anew
+fn new_players(game: &Game, connection: Option<Connection>, ai: Option<AiState>, difficulty: Difficulty) -> (Box<Player>, Box<Player>) {
+    match connection {
+        Some(connection) => (Box::new(human_player("You", "Opponent", false)), Box::new(RemotePlayer { connection : connection })),
+        None => match game.opponent {
+            Opponent::HUMAN => (Box::new(human_player("Player 1", "Player 2", true)), Box::new(human_player("Player 2", "Player 1", true))),
+            _ => {
+                let state = match ai {
+                    Some(state) => state,
+                    None => new_ai_state(difficulty, game.userfield.width, game.userfield.height, &game.fleet)
+                };
+                (Box::new(human_player("You", "Computer", false)), Box::new(AiPlayer { state : state }))
+            }
+        }
     }
-    return false;
+}
+
+fn save_path() -> PathBuf {
+    match env::home_dir() {
+        Some(home) => home.join(".battleship.save"),
+        None => PathBuf::from("battleship.save")
+    }
+}
+
+fn replay_path() -> PathBuf {
+    match env::home_dir() {
+        Some(home) => home.join(".battleship.replay"),
+        None => PathBuf::from("battleship.replay")
+    }
+}
+
+struct Args {
+    seed: Option<u64>,
+    network: Option<NetworkRole>,
+    headless: bool,
+    bots: Vec<String>,
+    difficulty: Difficulty,
+    timeout_ms: u64
+}
+
+// `--seed <number>` makes the first game repeat a reported one,
+// `--host <port>` and `--connect <host:port>` set up a network game,
+// `--headless` plays one game between `--bot <command>`s and built-in AIs
+fn parse_args() -> Result<Args, String> {
+    let args : Vec<String> = env::args().collect();
+    let mut parsed = Args {
+        seed : None,
+        network : None,
+        headless : false,
+        bots : vec![],
+        difficulty : Difficulty::HARD,
+        timeout_ms : 1000
+    };
+    let mut i = 1;
+    while i < args.len() {
+        if args[i] == "--seed" && i + 1 < args.len() {
+            match args[i+1].parse::<u64>() {
+                Ok(value) => { parsed.seed = Some(value); }
+                Err(_) => { return Err(format!("Bad seed '{}'", args[i+1])); }
+            }
+            i += 2;
+        }
+        else if args[i] == "--host" && i + 1 < args.len() {
+            match args[i+1].parse::<u16>() {
+                Ok(port) => { parsed.network = Some(NetworkRole::HOST(port)); }
+                Err(_) => { return Err(format!("Bad port '{}'", args[i+1])); }
+            }
+            i += 2;
+        }
+        else if args[i] == "--connect" && i + 1 < args.len() {
+            parsed.network = Some(NetworkRole::CONNECT(args[i+1].clone()));
+            i += 2;
+        }
+        else if args[i] == "--headless" {
+            parsed.headless = true;
+            i += 1;
+        }
+        else if args[i] == "--bot" && i + 1 < args.len() {
+            if parsed.bots.len() == 2 {
+                return Err("At most two bots can play".to_string());
+            }
+            parsed.bots.push(args[i+1].clone());
+            i += 2;
+        }
+        else if args[i] == "--difficulty" && i + 1 < args.len() {
+            parsed.difficulty = match &args[i+1][..] {
+                "easy" => Difficulty::EASY,
+                "hard" => Difficulty::HARD,
+                _ => { return Err(format!("Bad difficulty '{}'", args[i+1])); }
+            };
+            i += 2;
+        }
+        else if args[i] == "--timeout" && i + 1 < args.len() {
+            match args[i+1].parse::<u64>() {
+                Ok(value) => { parsed.timeout_ms = value; }
+                Err(_) => { return Err(format!("Bad timeout '{}'", args[i+1])); }
+            }
+            i += 2;
+        }
+        else {
+            return Err(format!("Unknown argument '{}'", args[i]));
+        }
+    }
+    return Ok(parsed);
+}
+
+// One game with default rules, bots take the first sides and the
+// built-in AI the rest. The result goes to stdout, the replay to the usual file
+fn run_headless(args: &Args) {
+    let fleets = builtin_fleets();
+    let rules = Rules {
+        width : 10,
+        height : 10,
+        adjacency : Adjacency::NO_TOUCHING,
+        mode : GameMode::CLASSIC,
+        fleet : fleets[0].clone()
+    };
+    let mut players : Vec<Box<Player>> = vec![];
+    for command in args.bots.iter() {
+        match spawn_bot(command, args.timeout_ms) {
+            Ok(bot) => { players.push(Box::new(bot)); }
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        }
+    }
+    while players.len() < 2 {
+        players.push(Box::new(ai_player(args.difficulty, rules.width, rules.height, &rules.fleet)));
+    }
+    let mut second = players.pop().unwrap();
+    let mut first = players.pop().unwrap();
+
+    let seed = args.seed.unwrap_or_else(random_seed);
+    let result = play_match(&mut *first, &mut *second, &rules, seed);
+    let (winner, loser) = match result.winner {
+        Turn::PLAYER => (first.name(), second.name()),
+        Turn::AI => (second.name(), first.name())
+    };
+    println!("Winner: {}", winner);
+    match result.forfeit {
+        Some(ref reason) => { println!("{} forfeited: {}", loser, reason); }
+        None => {}
+    }
+    let (fired, hit) = result.game.shot_stats(result.winner);
+    println!("Shots: {}, hits: {}", fired, hit);
+    println!("Seed: {}", seed);
+    let _ = save_replay_to_file(&game_replay(&result.game), &replay_path());
 }
 
 fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            println!("{}\nUsage: battleship [--seed <number>] [--host <port> | --connect <host:port>]\n       \
+                      battleship --headless [--bot <command>]... [--difficulty easy|hard] [--timeout <ms>] [--seed <number>]", e);
+            return;
+        }
+    };
+    if args.headless {
+        run_headless(&args);
+        return;
+    }
+    let mut seed = args.seed;
+    let network = args.network;
+
     initialize();
 
     let mut gamestatus : Status = Status::START;
 
-    let mut userfield = [[Cell { Type : CellType::EMPTY, Color : Color::WHITE }; 10]; 10];
-    let mut enemyfield = [[Cell { Type : CellType::EMPTY, Color : Color::WHITE }; 10]; 10];
-    let mut height : i32 = 0;
-    let mut width : i32 = 0;
-    let mut shotPos = Coord {x : 0, y : 0};
-    getmaxyx(stdscr, &mut height, &mut width);
+    let mut field_width : usize = 10;
+    let mut field_height : usize = 10;
+    let mut adjacency = Adjacency::NO_TOUCHING;
+    let mut mode = GameMode::CLASSIC;
+    let mut difficulty = Difficulty::EASY;
+    let mut opponent = if network.is_some() { Opponent::REMOTE } else { Opponent::COMPUTER };
+    let mut players : Option<(Box<Player>, Box<Player>)> = None;
+    // Why a finished network game can't be trusted
+    let mut cheating : Option<String> = None;
+    let fleets = builtin_fleets();
+    let mut fleet_index = 0;
+    let mut game = new_game(field_width, field_height, adjacency, &fleets[fleet_index], mode, opponent, random_seed());
+    let mut message = String::new();
 
     while gamestatus as i32 != Status::QUIT as i32 {
         match gamestatus {
             Status::START => {
                 // draw menu here
-                let qkey = 'q' as i32;
-                while gamestatus as i32 != Status::PLACE_SHIP as i32 {
-                    print_menu();
+                while gamestatus as i32 == Status::START as i32 {
+                    let has_save = save_path().exists();
+                    let has_replay = replay_path().exists();
+                    print_menu(difficulty, field_width, field_height, &fleets[fleet_index], adjacency, mode, &opponent_label(opponent, &network), has_save, has_replay, &message);
                     let mut ch = get_input();
                     match ch {
                         KEY_F1 => {
+                            game = new_game(field_width, field_height, adjacency, &fleets[fleet_index], mode, opponent, seed.take().unwrap_or_else(random_seed));
+                            cheating = None;
+                            message = String::new();
                             gamestatus = Status::PLACE_SHIP;
+                            let mut connection : Option<Connection> = None;
+                            match network {
+                                Some(ref role) => {
+                                    match start_network_game(role, &mut game) {
+                                        Ok(c) => { connection = Some(c); }
+                                        Err(e) => {
+                                            message = format!("Can't start a network game: {}", e);
+                                            gamestatus = Status::START;
+                                        }
+                                    }
+                                }
+                                None => {}
+                            }
+                            players = Some(new_players(&game, connection, None, difficulty));
+                        }
+                        ckey if ckey == 'c' as i32 && has_save => {
+                            match load_from_file(&save_path()) {
+                                Ok((saved, ai)) => {
+                                    game = saved;
+                                    players = Some(new_players(&game, None, ai, difficulty));
+                                    gamestatus = Status::PLAY;
+                                }
+                                Err(e) => {
+                                    message = format!("Can't load the game: {}", e);
+                                }
+                            }
+                        }
+                        rkey if rkey == 'r' as i32 && has_replay => {
+                            match load_replay_from_file(&replay_path()) {
+                                Ok(replay) => {
+                                    message = String::new();
+                                    watch_replay(&replay);
+                                }
+                                Err(e) => {
+                                    message = format!("Can't load the replay: {}", e);
+                                }
+                            }
+                        }
+                        fkey if fkey == 'f' as i32 => {
+                            fleet_index = (fleet_index + 1) % fleets.len();
+                        }
+                        mkey if mkey == 'm' as i32 => {
+                            mode = match mode {
+                                GameMode::CLASSIC => GameMode::SALVO,
+                                GameMode::SALVO => GameMode::FIXED_SALVO(5),
+                                GameMode::FIXED_SALVO(_) => GameMode::CLASSIC
+                            };
+                        }
+                        akey if akey == 'a' as i32 => {
+                            adjacency = match adjacency {
+                                Adjacency::NO_TOUCHING => Adjacency::CORNERS_ALLOWED,
+                                Adjacency::CORNERS_ALLOWED => Adjacency::TOUCHING_ALLOWED,
+                                Adjacency::TOUCHING_ALLOWED => Adjacency::NO_TOUCHING
+                            };
+                        }
+                        wkey if wkey == 'w' as i32 => {
+                            field_width = if field_width >= MAX_FIELD_SIZE { MIN_FIELD_SIZE } else { field_width + 1 };
+                        }
+                        hkey if hkey == 'h' as i32 => {
+                            field_height = if field_height >= MAX_FIELD_SIZE { MIN_FIELD_SIZE } else { field_height + 1 };
+                        }
+                        okey if okey == 'o' as i32 && network.is_none() => {
+                            opponent = match opponent {
+                                Opponent::COMPUTER => Opponent::HUMAN,
+                                _ => Opponent::COMPUTER
+                            };
+                        }
+                        dkey if dkey == 'd' as i32 => {
+                            difficulty = match difficulty {
+                                Difficulty::EASY => Difficulty::HARD,
+                                Difficulty::HARD => Difficulty::EASY
+                            };
                         }
                         KEY_F2 => {
                             print_about();
                             ch = get_input();
                         }
-                        KEY_F3 => {
-                            panic!("");
+                        qkey if qkey == 'q' as i32 || qkey == KEY_F3 => {
+                            gamestatus = Status::QUIT;
                         }
                         _ => {}
                     }
@@ -479,136 +1057,162 @@ fn main() {
             }
             Status::PLACE_SHIP => {
                 clear();
-                ai_place_ship(&mut enemyfield);
-                hide_ships(&mut enemyfield);
-                if DEBUG {
-                    ai_place_ship(&mut userfield);
-                    gamestatus = Status::PLAYER_TURN;
+                // It's only checked that the fleet fits, the players place it
+                let mut probe = game.enemyfield.clone();
+                if !ai_place_ship(&mut probe, &game.fleet, &mut game.rng.clone()) {
+                    message = "The fleet doesn't fit this field.".to_string();
+                    players = None;
+                    gamestatus = Status::START;
+                    continue;
                 }
-                else {
-                let mut curShip : Ship = Ship {
-                    coord : Coord {y : 1, x : 0},
-                    can_be_placed: true,
-                    cells : vec![ ShipCell { coord : Coord {y : -1, x : 0}, cell : CellType::SHIP },
-                                  ShipCell { coord : Coord {y : 0, x : 0}, cell : CellType::SHIP},
-                                  ShipCell { coord : Coord {y : 1, x : 0}, cell : CellType::SHIP},
-                                  ShipCell { coord : Coord {y : 2, x : 0}, cell : CellType::SHIP}]
+                game.turn = Turn::PLAYER;
+                let setup = match players {
+                    Some((ref mut first, ref mut second)) => {
+                        setup_game(&mut game, &mut **first, &mut **second).and_then(|_| {
+                            match second.connection() {
+                                Some(c) => {
+                                    print_waiting("Waiting for the opponent to place ships...");
+                                    exchange_ready(c, &game.userfield).map_err(|e| (Turn::AI, Failure::ERROR(e)))
+                                }
+                                None => Ok(())
+                            }
+                        })
+                    }
+                    None => Err((Turn::PLAYER, Failure::ERROR("No players".to_string())))
                 };
-
-                let mut before : Vec<ShipCell> = vec![];
-
-                remember_before(userfield, &curShip, &mut before);
-                add_ship(&mut userfield, &curShip);
-
-                print_field(userfield, height/2-5, width/2-30);
-                print_field(enemyfield, height/2-5, width/2+5);
-
-                let mut count = 0;
-                let mut ch = getch();
-
-                // TODO: rewrite this, 'count' is bad solution
-                loop {
-                    if ch == ('q' as i32) {
+                match setup {
+                    Ok(()) => {
+                        // Host shoots first
+                        match network {
+                            Some(NetworkRole::CONNECT(_)) => { game.turn = Turn::AI; }
+                            _ => {}
+                        }
+                        gamestatus = Status::PLAY;
+                    }
+                    Err((_, Failure::QUIT)) => {
+                        players = None;
                         gamestatus = Status::QUIT;
-                        break;
                     }
-                    remove_ship(&mut userfield, &curShip);
-                    let shipWasPlaced = move_ship(&mut userfield, &mut curShip, ch);
-                    place_before(&mut userfield, &before);
-                    remember_before(userfield, &curShip, &mut before);
-                    if (shipWasPlaced) {
-                        add_ship(&mut userfield, &curShip);
-                        count+=1;
-                        if count <= 2 {
-                            curShip = Ship {
-                                coord : Coord {y : 1, x : 0},
-                                can_be_placed: true,
-                                cells : vec![ ShipCell { coord : Coord {y : -1, x : 0}, cell : CellType::SHIP},
-                                              ShipCell { coord : Coord {y : 0, x : 0}, cell : CellType::SHIP},
-                                              ShipCell { coord : Coord {y : 1, x : 0}, cell : CellType::SHIP}]
-                            };
-                            remember_before(userfield, &curShip, &mut before);
-                            collision(&userfield, &mut curShip);
-                            add_ship(&mut userfield, &curShip);
-                        }
-                        else if count <= 5 {
-                            curShip = Ship {
-                                coord : Coord {y : 0, x : 0},
-                                can_be_placed: true,
-                                cells : vec![ ShipCell { coord : Coord {y : 0, x : 0}, cell : CellType::SHIP},
-                                              ShipCell { coord : Coord {y : 1, x : 0}, cell : CellType::SHIP}]
-                            };
-                            remember_before(userfield, &curShip, &mut before);
-                            collision(&userfield, &mut curShip);
-                            add_ship(&mut userfield, &curShip);
-                        }
-                        else if count <= 9 {
-                            curShip = Ship {
-                                coord : Coord {y : 0, x : 0},
-                                can_be_placed: true,
-                                cells : vec![ ShipCell { coord : Coord {y : 0, x : 0}, cell : CellType::SHIP}]
-                            };
-                            remember_before(userfield, &curShip, &mut before);
-                            collision(&userfield, &mut curShip);
-                            add_ship(&mut userfield, &curShip);
-                        }
-                        else {
-                            gamestatus = Status::PLAYER_TURN;
-                            break;
+                    Err((_, Failure::ERROR(e))) => {
+                        message = format!("Game is over: {}", e);
+                        players = None;
+                        gamestatus = Status::START;
+                    }
+                }
+            }
+            Status::PLAY => {
+                let outcome = match players {
+                    Some((ref mut first, ref mut second)) => play_game(&mut game, &mut **first, &mut **second),
+                    None => Err((Turn::PLAYER, Failure::ERROR("No players".to_string())))
+                };
+                match outcome {
+                    Ok(_) => { gamestatus = Status::GAME_OVER; }
+                    // Network games can't be resumed, the opponent is told we left
+                    Err((_, Failure::QUIT)) if game.opponent == Opponent::REMOTE => {
+                        players = None;
+                        gamestatus = Status::QUIT;
+                    }
+                    Err((_, Failure::QUIT)) => {
+                        // Saved game continues with what the AI has learned
+                        let ai = match players {
+                            Some((_, ref second)) => second.ai_state(),
+                            None => None
+                        };
+                        gamestatus = Status::QUIT;
+                        match save_to_file(&game, ai, &save_path()) {
+                            Ok(_) => {}
+                            Err(e) => {
+                                print_waiting(&format!("Can't save the game: {}. Quit anyway? (y/n)", e));
+                                if getch() != 'y' as i32 {
+                                    gamestatus = Status::PLAY;
+                                }
+                            }
                         }
                     }
-                    else {
-                        add_ship(&mut userfield, &curShip);
+                    Err((_, Failure::ERROR(e))) => {
+                        message = format!("Network game is over: {}", e);
+                        players = None;
+                        gamestatus = Status::START;
                     }
-
-                    print_field(userfield, height/2-5, width/2-30);
-                    print_field(enemyfield, height/2-5, width/2+5);
-
-                    // Need refresh after each frame?
-                    refresh();
-                    ch = getch();
-                }
                 }
             }
-            Status::PLAYER_TURN => {
-                clear();
-                print_field(userfield, height/2-5, width/2-30);
-                print_field(enemyfield, height/2-5, width/2+5);
-                let mut ch = getch();
-                match ch {
-                    KEY_LEFT => {
-                        if shotPos.x as i32 - 1 as i32 >= 0 {
-                            shotPos.x-=1;
+            Status::GAME_OVER => {
+                let winner = if game.player_won() { Turn::PLAYER } else { Turn::AI };
+                // Network opponent's results are only trusted after its fleet is checked
+                let verdict = match players {
+                    Some((ref mut first, ref mut second)) => {
+                        let _ = finish_game(&mut **first, &mut **second, winner);
+                        match second.connection() {
+                            Some(c) => reveal_and_verify(c, &game).map(Some),
+                            None => Ok(None)
                         }
                     }
-                    KEY_RIGHT => {
-                        if shotPos.x as i32 + 1 as i32 <= 9 {
-                            shotPos.x+=1;
-                        }
+                    None => Ok(None)
+                };
+                players = None;
+                // Finished game can't be continued, but it can be watched again.
+                // A network game only if the opponent's fleet is known
+                let _ = fs::remove_file(save_path());
+                let replay = match verdict {
+                    Err(e) => {
+                        cheating = Some(e);
+                        None
                     }
-                    KEY_UP => {
-                        if shotPos.y as i32 - 1 as i32 >= 0 {
-                            shotPos.y-=1;
-                        }
+                    Ok(Some(revealed)) => Some(revealed_replay(&game, &revealed)),
+                    Ok(None) if game.opponent == Opponent::REMOTE => None,
+                    Ok(None) => Some(game_replay(&game))
+                };
+                match replay {
+                    Some(ref replay) => { let _ = save_replay_to_file(replay, &replay_path()); }
+                    None => {}
+                }
+                let title = match cheating {
+                    Some(ref e) => e.clone(),
+                    None => match (game.opponent, winner) {
+                    (Opponent::HUMAN, _) => format!("{} wins!", player_name(game.opponent, winner)),
+                    (_, Turn::PLAYER) => "You win!".to_string(),
+                    (_, Turn::AI) => "You lose!".to_string()
                     }
-                    KEY_DOWN => {
-                        if shotPos.y as i32 + 1 as i32 >= 0 {
-                            shotPos.y+=1;
-                        }
+                };
+                // Unless it's hot seat, it's always the player's own numbers
+                let (shots_fired, shots_hit) = match game.opponent {
+                    Opponent::HUMAN => game.shot_stats(winner),
+                    _ => game.shot_stats(Turn::PLAYER)
+                };
+                // Stays on this screen until it's left, the replay can be watched any number of times
+                let mut ch;
+                loop {
+                    print_game_over(&title, shots_fired, shots_hit, game.rng.seed, replay.is_some());
+                    ch = get_input();
+                    if ch == KEY_F1 || ch == KEY_F2 || ch == ('q' as i32) {
+                        break;
                     }
-                    KEY_F1 => {
-                        if !tryToShot(&mut enemyfield, shotPos.y, shotPos.x) {
-                            // gamestatus = AI_TURN;
+                    if ch == ('r' as i32) {
+                        match replay {
+                            Some(ref replay) => { watch_replay(replay); }
+                            None => {}
                         }
                     }
-                    _ => {}
                 }
-                enemyfield[shotPos.y as usize][shotPos.x as usize].Type = CellType::SHOT_POS;
-            }
-            Status::AI_TURN => {
-                clear();
-                printw("Ai turn!\n");
-                gamestatus = Status::PLAYER_TURN;
+                if ch == KEY_F1 && network.is_some() {
+                    // A new network game needs the other side to connect again
+                    message = "Press <F1> when the opponent is ready.".to_string();
+                    gamestatus = Status::START;
+                }
+                else if ch == KEY_F1 {
+                    game = new_game(field_width, field_height, adjacency, &fleets[fleet_index], mode, opponent, seed.take().unwrap_or_else(random_seed));
+                    players = Some(new_players(&game, None, None, difficulty));
+                    cheating = None;
+                    message = String::new();
+                    gamestatus = Status::PLACE_SHIP;
+                }
+                else if ch == KEY_F2 {
+                    message = String::new();
+                    gamestatus = Status::START;
+                }
+                else if ch == ('q' as i32) {
+                    gamestatus = Status::QUIT;
+                }
             }
             _ => {}
         }
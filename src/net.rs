@@ -0,0 +1,496 @@
+// Games between two machines over TCP. Both sides send one message per line,
+// words are separated by spaces, coordinates are 0-based "y x" pairs.
+//
+//   HELLO <version>                 both sides first, versions must match
+//   RULES <width> <height> <adjacency> <mode>
+//   FLEET <name>
+//   SHIP <shape> <count> <name>     one line per ship type
+//   END                             host sends the rules above, guest plays by them
+//   COMMIT <hash>                   both sides, once their fleet is placed
+//   READY                           right after COMMIT
+//   SHOT <y> <x>                    side whose turn it is
+//   RESULT miss|hit|already-shot|out-of-bounds
+//   RESULT sunk <y>,<x> ...         answer to SHOT, sunk lists the whole ship
+//   REVEAL <salt> <ship> ...        both sides when the game is decided
+//   BYE                             either side leaves the game
+//
+// A straight ship's shape is its length, any other shape is written as its
+// cells when upright, "<y>,<x>;<y>,<x>...". Adjacency and mode are written as
+// in save files: no-touching, corners or touching; classic, salvo or
+// "salvo <shots>". The host shoots first. In the
+// classic mode a side keeps shooting while it hits, in salvo modes it fires
+// all of its shots. Shots answered with already-shot or out-of-bounds don't
+// count and are fired again; a legal shot answered that way is cheating.
+//
+// Nobody can check a RESULT during the game, so fleets are committed to.
+// A ship is written as its cells, "<y>,<x>;<y>,<x>...", ships are separated by
+// spaces and listed in the order they were placed. The commitment is the hex
+// SHA-256 of "<salt> <ships>", where the salt is random hex. After the game
+// each side reveals the salt and the ships, and the other one checks the
+// hash, the placement rules and every result it was given.
+
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+
+use rand;
+use rand::Rng;
+
+use field::*;
+use fleet::*;
+use game::*;
+use save::*;
+use rng::*;
+use player::*;
+use sha256::*;
+
+pub static PROTOCOL_VERSION: u32 = 3;
+
+pub enum Message {
+    HELLO(u32),
+    RULES(usize, usize, Adjacency, GameMode),
+    FLEET(String),
+    SHIP(ShipType),
+    END,
+    COMMIT(String),
+    READY,
+    SHOT(Coord),
+    RESULT(ShotResult),
+    // Salt and ships
+    REVEAL(String, String),
+    BYE,
+}
+
+// Settings of a network game, chosen by the host
+pub struct Rules {
+    pub width: usize,
+    pub height: usize,
+    pub adjacency: Adjacency,
+    pub mode: GameMode,
+    pub fleet: Fleet
+}
+
+pub struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    // Own fleet as committed to, and the opponent's commitment
+    salt: String,
+    layout: String,
+    commitment: String
+}
+
+pub fn format_message(message: &Message) -> String {
+    match *message {
+        Message::HELLO(version) => format!("HELLO {}", version),
+        Message::RULES(width, height, adjacency, mode) => format!("RULES {} {} {} {}", width, height, adjacency_name(adjacency), mode_name(mode)),
+        Message::FLEET(ref name) => format!("FLEET {}", name),
+        Message::SHIP(ref ship) => format!("SHIP {}", ship_line(ship)),
+        Message::END => "END".to_string(),
+        Message::COMMIT(ref hash) => format!("COMMIT {}", hash),
+        Message::READY => "READY".to_string(),
+        Message::SHOT(coord) => format!("SHOT {} {}", coord.y, coord.x),
+        Message::RESULT(ShotResult::SUNK(ref cells)) => {
+            let cells : Vec<String> = cells.iter().map(|cell| format!("{},{}", cell.y, cell.x)).collect();
+            format!("RESULT sunk {}", cells.join(" "))
+        }
+        Message::RESULT(ref result) => format!("RESULT {}", outcome_name(result)),
+        Message::REVEAL(ref salt, ref layout) => format!("REVEAL {} {}", salt, layout),
+        Message::BYE => "BYE".to_string()
+    }
+}
+
+pub fn parse_message(line: &str) -> Result<Message, String> {
+    let mut tokens = line.split_whitespace();
+    let message = match tokens.next() {
+        Some("HELLO") => Message::HELLO(try!(parse_number(tokens.next(), line))),
+        Some("RULES") => {
+            let width = try!(parse_number(tokens.next(), line));
+            let height = try!(parse_number(tokens.next(), line));
+            let adjacency = try!(parse_adjacency(tokens.next(), line));
+            let mode = try!(parse_mode(&mut tokens, line));
+            Message::RULES(width, height, adjacency, mode)
+        }
+        Some("FLEET") => Message::FLEET(tokens.collect::<Vec<&str>>().join(" ")),
+        Some("SHIP") => Message::SHIP(try!(parse_ship_line(&mut tokens, line))),
+        Some("END") => Message::END,
+        Some("COMMIT") => match tokens.next() {
+            Some(hash) => Message::COMMIT(hash.to_string()),
+            None => { return Err(format!("Missing hash in '{}'", line)); }
+        },
+        Some("READY") => Message::READY,
+        Some("SHOT") => {
+            let y = try!(parse_number(tokens.next(), line));
+            let x = try!(parse_number(tokens.next(), line));
+            Message::SHOT(Coord { y : y, x : x })
+        }
+        Some("RESULT") => {
+            let result = match tokens.next() {
+                Some("miss") => ShotResult::MISS,
+                Some("hit") => ShotResult::HIT,
+                Some("already-shot") => ShotResult::ALREADY_SHOT,
+                Some("out-of-bounds") => ShotResult::OUT_OF_BOUNDS,
+                Some("sunk") => {
+                    let mut cells : Vec<Coord> = vec![];
+                    for token in tokens {
+                        let mut parts = token.split(',');
+                        let y = try!(parse_number(parts.next(), line));
+                        let x = try!(parse_number(parts.next(), line));
+                        cells.push(Coord { y : y, x : x });
+                    }
+                    if cells.len() == 0 {
+                        return Err(format!("Sunk ship without cells in '{}'", line));
+                    }
+                    ShotResult::SUNK(cells)
+                }
+                _ => { return Err(format!("Bad result in '{}'", line)); }
+            };
+            Message::RESULT(result)
+        }
+        Some("REVEAL") => match tokens.next() {
+            Some(salt) => Message::REVEAL(salt.to_string(), tokens.collect::<Vec<&str>>().join(" ")),
+            None => { return Err(format!("Missing salt in '{}'", line)); }
+        },
+        Some("BYE") => Message::BYE,
+        _ => { return Err(format!("Unknown message '{}'", line)); }
+    };
+    return Ok(message);
+}
+
+fn connection(stream: TcpStream) -> io::Result<Connection> {
+    let reader = BufReader::new(try!(stream.try_clone()));
+    Ok(Connection {
+        reader : reader,
+        writer : stream,
+        salt : String::new(),
+        layout : String::new(),
+        commitment : String::new()
+    })
+}
+
+// Waits for one player to connect
+pub fn host(port: u16) -> io::Result<Connection> {
+    let listener = try!(TcpListener::bind(("0.0.0.0", port)));
+    let (stream, _) = try!(listener.accept());
+    connection(stream)
+}
+
+pub fn connect(address: &str) -> io::Result<Connection> {
+    connection(try!(TcpStream::connect(address)))
+}
+
+impl Connection {
+    pub fn send(&mut self, message: &Message) -> Result<(), String> {
+        let line = format!("{}\n", format_message(message));
+        self.writer.write_all(line.as_bytes()).and_then(|_| self.writer.flush()).map_err(|e| e.to_string())
+    }
+
+    pub fn receive(&mut self) -> Result<Message, String> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => Err("Connection closed".to_string()),
+            Ok(_) => parse_message(line.trim()),
+            Err(e) => Err(e.to_string())
+        }
+    }
+
+    // Anything but the expected message ends the game
+    fn receive_game_message(&mut self) -> Result<Message, String> {
+        match try!(self.receive()) {
+            Message::BYE => Err("Opponent left the game".to_string()),
+            message => Ok(message)
+        }
+    }
+}
+
+fn check_hello(connection: &mut Connection) -> Result<(), String> {
+    match try!(connection.receive_game_message()) {
+        Message::HELLO(version) if version == PROTOCOL_VERSION => Ok(()),
+        Message::HELLO(version) => Err(format!("Opponent speaks protocol version {}, not {}", version, PROTOCOL_VERSION)),
+        _ => Err("Expected HELLO".to_string())
+    }
+}
+
+pub fn host_handshake(connection: &mut Connection, rules: &Rules) -> Result<(), String> {
+    try!(connection.send(&Message::HELLO(PROTOCOL_VERSION)));
+    try!(check_hello(connection));
+    try!(connection.send(&Message::RULES(rules.width, rules.height, rules.adjacency, rules.mode)));
+    try!(connection.send(&Message::FLEET(rules.fleet.name.clone())));
+    for ship in rules.fleet.ships.iter() {
+        try!(connection.send(&Message::SHIP(ship.clone())));
+    }
+    connection.send(&Message::END)
+}
+
+pub fn guest_handshake(connection: &mut Connection) -> Result<Rules, String> {
+    try!(connection.send(&Message::HELLO(PROTOCOL_VERSION)));
+    try!(check_hello(connection));
+    let mut rules = match try!(connection.receive_game_message()) {
+        Message::RULES(width, height, adjacency, mode) => Rules {
+            width : width,
+            height : height,
+            adjacency : adjacency,
+            mode : mode,
+            fleet : Fleet { name : String::new(), ships : vec![] }
+        },
+        _ => { return Err("Expected RULES".to_string()); }
+    };
+    loop {
+        match try!(connection.receive_game_message()) {
+            Message::FLEET(name) => { rules.fleet.name = name; }
+            Message::SHIP(ship) => { rules.fleet.ships.push(ship); }
+            Message::END => { break; }
+            _ => { return Err("Expected the fleet".to_string()); }
+        }
+        if rules.fleet.ships.len() > MAX_FIELD_SIZE * MAX_FIELD_SIZE {
+            return Err("Opponent sent too many ship types".to_string());
+        }
+    }
+    if rules.width < MIN_FIELD_SIZE || rules.width > MAX_FIELD_SIZE || rules.height < MIN_FIELD_SIZE || rules.height > MAX_FIELD_SIZE {
+        return Err(format!("Unsupported field size {}x{}", rules.width, rules.height));
+    }
+    try!(check_fleet(&rules.fleet, rules.width, rules.height));
+    if fleet_size(&rules.fleet) == 0 {
+        return Err("Opponent sent an empty fleet".to_string());
+    }
+    return Ok(rules);
+}
+
+pub fn commitment(salt: &str, layout: &str) -> String {
+    to_hex(&sha256(format!("{} {}", salt, layout).as_bytes()))
+}
+
+// Not from the game RNG: its seed is shown to the player, the salt must not be guessable
+fn new_salt() -> String {
+    let bytes : Vec<u8> = (0..16).map(|_| rand::thread_rng().gen::<u8>()).collect();
+    to_hex(&bytes)
+}
+
+fn same_result(real: &ShotResult, reported: &ShotResult) -> bool {
+    match (real, reported) {
+        (&ShotResult::SUNK(ref real), &ShotResult::SUNK(ref reported)) => {
+            real.len() == reported.len() && real.iter().all(|cell| reported.contains(cell))
+        }
+        _ => outcome_name(real) == outcome_name(reported)
+    }
+}
+
+// Checks the opponent's revealed fleet against its commitment and everything it
+// reported, the fleet is returned as placed
+pub fn verify_reveal(theirs: &str, salt: &str, layout: &str, game: &Game) -> Result<Field, String> {
+    if commitment(salt, layout) != theirs {
+        return Err("Cheating detected: revealed fleet doesn't match its commitment".to_string());
+    }
+    let ships = try!(parse_layout(layout));
+    let field = game.enemyfield.clone();
+    let revealed = try!(layout_field(&ships, field.width, field.height, field.adjacency, &game.fleet)
+                         .map_err(|e| format!("Cheating detected: {}", e)));
+    let mut field = revealed.clone();
+    for shot in game.history.iter().filter(|shot| shot.turn == Turn::PLAYER) {
+        let real = tryToShot(&mut field, shot.coord.pos());
+        if !same_result(&real, &shot.result) {
+            return Err(format!("Cheating detected: shot at {},{} was a {}, but reported as a {}",
+                               shot.coord.y, shot.coord.x, outcome_name(&real), outcome_name(&shot.result)));
+        }
+    }
+    return Ok(revealed);
+}
+
+// Commits to the own fleet and returns once both fleets are placed
+pub fn exchange_ready(connection: &mut Connection, field: &Field) -> Result<(), String> {
+    connection.salt = new_salt();
+    connection.layout = layout_string(field);
+    let hash = commitment(&connection.salt, &connection.layout);
+    try!(connection.send(&Message::COMMIT(hash)));
+    try!(connection.send(&Message::READY));
+    match try!(connection.receive_game_message()) {
+        Message::COMMIT(hash) => { connection.commitment = hash; }
+        _ => { return Err("Expected COMMIT".to_string()); }
+    }
+    match try!(connection.receive_game_message()) {
+        Message::READY => Ok(()),
+        _ => Err("Expected READY".to_string())
+    }
+}
+
+// Both sides show their fleets once the game is decided. The opponent's fleet
+// comes back as placed, before any shot
+pub fn reveal_and_verify(connection: &mut Connection, game: &Game) -> Result<Field, String> {
+    let reveal = Message::REVEAL(connection.salt.clone(), connection.layout.clone());
+    try!(connection.send(&reveal));
+    match connection.receive() {
+        Ok(Message::REVEAL(salt, layout)) => verify_reveal(&connection.commitment, &salt, &layout, game),
+        Ok(Message::BYE) | Err(_) => Err("Cheating detected: opponent left without revealing its fleet".to_string()),
+        Ok(_) => Err("Expected REVEAL".to_string())
+    }
+}
+
+pub fn send_shot(connection: &mut Connection, field: &Field, target: Coord) -> Result<ShotResult, String> {
+    try!(connection.send(&Message::SHOT(target)));
+    let result = match try!(connection.receive_game_message()) {
+        Message::RESULT(result) => result,
+        _ => { return Err("Expected RESULT".to_string()); }
+    };
+    match result {
+        // The shot is never at a cell known to be shot, so it can't be refused
+        ShotResult::ALREADY_SHOT | ShotResult::OUT_OF_BOUNDS => {
+            return Err("Cheating detected: opponent refused a legal shot".to_string());
+        }
+        ShotResult::SUNK(ref cells) => {
+            for cell in cells.iter() {
+                if !field.contains(cell.y as i32, cell.x as i32) {
+                    return Err(format!("Opponent sunk a ship at {},{} outside the field", cell.y, cell.x));
+                }
+            }
+            if !cells.contains(&target) {
+                return Err("Opponent sunk a ship that wasn't shot".to_string());
+            }
+        }
+        _ => {}
+    }
+    return Ok(result);
+}
+
+pub fn receive_shot(connection: &mut Connection) -> Result<Coord, String> {
+    match try!(connection.receive_game_message()) {
+        Message::SHOT(target) => Ok(target),
+        _ => Err("Expected SHOT".to_string())
+    }
+}
+
+pub fn send_result(connection: &mut Connection, result: &ShotResult) -> Result<(), String> {
+    connection.send(&Message::RESULT(result.clone()))
+}
+
+// Opponent on the other machine as one side of a game, see `player`. Its
+// fleet stays there, so shots at it are answered over the connection. Fleets
+// are committed to and revealed by whoever runs the game, not by this player
+pub struct RemotePlayer {
+    pub connection: Connection
+}
+
+impl Player for RemotePlayer {
+    fn name(&self) -> String {
+        "Opponent".to_string()
+    }
+
+    fn place_fleet(&mut self, _own: &mut Field, _fleet: &Fleet, _rng: &mut GameRng) -> Result<(), Failure> {
+        Ok(())
+    }
+
+    fn choose_shots(&mut self, _own: &Field, _target: &OpponentView, _shots: usize, _rng: &mut GameRng) -> Result<Vec<Coord>, Failure> {
+        // Every shot waits for its result, so they come one by one
+        receive_shot(&mut self.connection).map(|target| vec![target]).map_err(Failure::ERROR)
+    }
+
+    fn resolve(&mut self, own: &mut Field, target: Coord) -> Result<ShotResult, Failure> {
+        let result = try!(send_shot(&mut self.connection, own, target).map_err(Failure::ERROR));
+        apply_result(own, target.y, target.x, &result);
+        Ok(result)
+    }
+
+    fn observe(&mut self, _own: &Field, _target: &OpponentView, _coord: Coord, result: &ShotResult) -> Result<(), Failure> {
+        send_result(&mut self.connection, result).map_err(Failure::ERROR)
+    }
+
+    fn connection(&mut self) -> Option<&mut Connection> {
+        Some(&mut self.connection)
+    }
+}
+
+// The opponent is told we left, whenever the game ends
+impl Drop for RemotePlayer {
+    fn drop(&mut self) {
+        let _ = self.connection.send(&Message::BYE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ai::*;
+
+    #[test]
+    fn messages_survive_a_round_trip() {
+        let lines = ["HELLO 3",
+                     "RULES 10 12 corners salvo 3",
+                     "RULES 10 10 no-touching classic",
+                     "FLEET Milton Bradley",
+                     "SHIP 4 1 Battleship",
+                     "SHIP 0,0;1,0;1,1 2 Corner boat",
+                     "END",
+                     "COMMIT 0123abcd",
+                     "READY",
+                     "SHOT 4 7",
+                     "RESULT miss",
+                     "RESULT hit",
+                     "RESULT already-shot",
+                     "RESULT out-of-bounds",
+                     "RESULT sunk 1,2 1,3 1,4",
+                     "REVEAL 00ff 0,0;1,0 5,5",
+                     "BYE"];
+        for line in lines.iter() {
+            let message = parse_message(line).unwrap();
+            assert_eq!(format_message(&message), *line);
+        }
+    }
+
+    #[test]
+    fn bad_messages_are_rejected() {
+        for line in ["", "HELLO", "SHOT 1", "RESULT", "RESULT sunk", "RESULT maybe", "RULES 10 10 corners salvo 0", "COMMIT", "WAVE"].iter() {
+            assert!(parse_message(line).is_err());
+        }
+    }
+
+    // Game where the player fired at every cell of the first rows of an honest
+    // opponent, the results are the real ones
+    fn played_game(layout: &str) -> Game {
+        let fleet = russian_fleet();
+        let mut game = new_game(10, 10, Adjacency::NO_TOUCHING, &fleet, GameMode::CLASSIC, Opponent::REMOTE, 1);
+        let mut field = layout_field(&parse_layout(layout).unwrap(), 10, 10, Adjacency::NO_TOUCHING, &fleet).unwrap();
+        for y in 0..3 {
+            for x in 0..10 {
+                let coord = Coord { y : y, x : x };
+                let result = tryToShot(&mut field, coord.pos());
+                match result {
+                    ShotResult::ALREADY_SHOT => {}
+                    _ => { game.record_shot(Turn::PLAYER, coord, &result); }
+                }
+            }
+        }
+        return game;
+    }
+
+    fn placed_layout(seed: u64) -> String {
+        let mut field = empty_field(10, 10, Adjacency::NO_TOUCHING);
+        assert!(ai_place_ship(&mut field, &russian_fleet(), &mut seeded_rng(seed)));
+        return layout_string(&field);
+    }
+
+    #[test]
+    fn honest_reveal_is_accepted() {
+        let layout = placed_layout(5);
+        let game = played_game(&layout);
+        let revealed = verify_reveal(&commitment("00ff", &layout), "00ff", &layout, &game).unwrap();
+        assert_eq!(layout_string(&revealed), layout);
+    }
+
+    #[test]
+    fn lying_reveal_is_rejected() {
+        let layout = placed_layout(5);
+        let mut game = played_game(&layout);
+        let hash = commitment("00ff", &layout);
+
+        // Another fleet than the one committed to
+        let other = placed_layout(6);
+        assert!(verify_reveal(&hash, "00ff", &other, &game).is_err());
+        assert!(verify_reveal(&hash, "00fe", &layout, &game).is_err());
+
+        // A hit reported as a miss
+        let index = game.history.iter().position(|shot| match shot.result { ShotResult::HIT | ShotResult::SUNK(_) => true, _ => false });
+        match index {
+            Some(index) => { game.history[index].result = ShotResult::MISS; }
+            None => { game.history[0].result = ShotResult::HIT; }
+        }
+        assert!(verify_reveal(&hash, "00ff", &layout, &game).is_err());
+    }
+}
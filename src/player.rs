@@ -0,0 +1,194 @@
+// One side of a game: a human at the keyboard, the built-in AI, a bot or a
+// network peer. The game loop below only talks to players through this trait,
+// so any two of them can play each other. A player gets its own field, but of
+// the other side only an `OpponentView`.
+
+use field::*;
+use fleet::*;
+use game::*;
+use net::*;
+use ai::*;
+use rng::*;
+
+pub enum Failure {
+    // A human left the game
+    QUIT,
+    // Broken rules or a lost connection
+    ERROR(String),
+}
+
+pub trait Player {
+    fn name(&self) -> String;
+
+    // Called before placing, `side` is the side of the player's fleet
+    fn start(&mut self, _rules: &Rules, _side: Turn) -> Result<(), Failure> {
+        Ok(())
+    }
+
+    fn place_fleet(&mut self, own: &mut Field, fleet: &Fleet, rng: &mut GameRng) -> Result<(), Failure>;
+
+    // Up to `shots` cells of `target` to shoot at, the rest is asked for again
+    fn choose_shots(&mut self, own: &Field, target: &OpponentView, shots: usize, rng: &mut GameRng) -> Result<Vec<Coord>, Failure>;
+
+    // Opponent's shot at the own fleet. Only a network peer knows its fleet,
+    // everyone else's shots are resolved on the local field
+    fn resolve(&mut self, own: &mut Field, target: Coord) -> Result<ShotResult, Failure> {
+        Ok(tryToShot(own, target.pos()))
+    }
+
+    // Result of the player's own shot
+    fn observe(&mut self, _own: &Field, _target: &OpponentView, _coord: Coord, _result: &ShotResult) -> Result<(), Failure> {
+        Ok(())
+    }
+
+    // The player's turn is over and the other side shoots next
+    fn end_turn(&mut self, _own: &Field, _target: &OpponentView) -> Result<(), Failure> {
+        Ok(())
+    }
+
+    // Result of the opponent's shot at the own fleet
+    fn incoming(&mut self, _own: &Field, _target: &OpponentView, _coord: Coord, _result: &ShotResult) -> Result<(), Failure> {
+        Ok(())
+    }
+
+    fn game_over(&mut self, _won: bool) -> Result<(), Failure> {
+        Ok(())
+    }
+
+    // What a built-in AI has learned, saved games continue with it
+    fn ai_state(&self) -> Option<&AiState> {
+        None
+    }
+
+    // Connection to a network peer. Whoever runs the game commits to the own
+    // fleet and checks the peer's over it, see `net`
+    fn connection(&mut self) -> Option<&mut Connection> {
+        None
+    }
+}
+
+pub fn game_rules(game: &Game) -> Rules {
+    Rules {
+        width : game.userfield.width,
+        height : game.userfield.height,
+        adjacency : game.userfield.adjacency,
+        mode : game.mode,
+        fleet : game.fleet.clone()
+    }
+}
+
+// Both fleets get placed, errors come with the side that failed
+pub fn setup_game(game: &mut Game, first: &mut Player, second: &mut Player) -> Result<(), (Turn, Failure)> {
+    let rules = game_rules(game);
+    try!(first.start(&rules, Turn::PLAYER).map_err(|e| (Turn::PLAYER, e)));
+    try!(second.start(&rules, Turn::AI).map_err(|e| (Turn::AI, e)));
+    try!(first.place_fleet(&mut game.userfield, &rules.fleet, &mut game.rng).map_err(|e| (Turn::PLAYER, e)));
+    try!(second.place_fleet(&mut game.enemyfield, &rules.fleet, &mut game.rng).map_err(|e| (Turn::AI, e)));
+    return Ok(());
+}
+
+// One salvo, or a single shot in the classic mode
+fn play_turn(game: &mut Game, shooter: &mut Player, defender: &mut Player) -> Result<Vec<ShotResult>, (Turn, Failure)> {
+    let turn = game.turn;
+    let shots = game.salvo_size_of(turn);
+    let mut results : Vec<ShotResult> = vec![];
+    while results.len() < shots {
+        let targets = {
+            let (own, target) = match turn {
+                Turn::PLAYER => (&game.userfield, &game.enemyfield),
+                Turn::AI => (&game.enemyfield, &game.userfield)
+            };
+            try!(shooter.choose_shots(own, &opponent_view(target), shots - results.len(), &mut game.rng).map_err(|e| (turn, e)))
+        };
+        if targets.len() == 0 {
+            return Err((turn, Failure::ERROR("no shot chosen".to_string())));
+        }
+        for &target in targets.iter().take(shots - results.len()) {
+            // Cells known to be shot, like the ring around a ship sunk earlier
+            // in the same salvo, don't reach the defender and are asked for again
+            let known = {
+                let field = match turn {
+                    Turn::PLAYER => &game.enemyfield,
+                    Turn::AI => &game.userfield
+                };
+                match field.cell(target.pos()) {
+                    None => Some(ShotResult::OUT_OF_BOUNDS),
+                    Some(cell) => match cell.Type {
+                        CellType::SHOT | CellType::COLLISION_SHIP => Some(ShotResult::ALREADY_SHOT),
+                        _ => None
+                    }
+                }
+            };
+            match known {
+                Some(result) => {
+                    let (own, target_field) = game.fields_of(turn);
+                    try!(shooter.observe(own, &opponent_view(target_field), target, &result).map_err(|e| (turn, e)));
+                    continue;
+                }
+                None => {}
+            }
+            let result = {
+                let field = match turn {
+                    Turn::PLAYER => &mut game.enemyfield,
+                    Turn::AI => &mut game.userfield
+                };
+                try!(defender.resolve(field, target).map_err(|e| (other_turn(turn), e)))
+            };
+            game.record_shot(turn, target, &result);
+            {
+                let (own, target_field) = game.fields_of(turn);
+                try!(shooter.observe(own, &opponent_view(target_field), target, &result).map_err(|e| (turn, e)));
+                try!(defender.incoming(target_field, &opponent_view(own), target, &result).map_err(|e| (other_turn(turn), e)));
+            }
+            match result {
+                // Nothing happened, it's asked for again
+                ShotResult::ALREADY_SHOT | ShotResult::OUT_OF_BOUNDS => {}
+                _ => { results.push(result); }
+            }
+            if game.is_over() {
+                return Ok(results);
+            }
+        }
+    }
+    return Ok(results);
+}
+
+// Plays from `game.turn` until one side wins
+pub fn play_game(game: &mut Game, first: &mut Player, second: &mut Player) -> Result<Turn, (Turn, Failure)> {
+    loop {
+        if game.player_won() {
+            return Ok(Turn::PLAYER);
+        }
+        if game.ai_won() {
+            return Ok(Turn::AI);
+        }
+        let turn = game.turn;
+        let results = match turn {
+            Turn::PLAYER => try!(play_turn(game, first, second)),
+            Turn::AI => try!(play_turn(game, second, first))
+        };
+        // In the classic mode a hit earns another shot
+        let again = match (game.mode, results.last()) {
+            (GameMode::CLASSIC, Some(&ShotResult::HIT)) | (GameMode::CLASSIC, Some(&ShotResult::SUNK(_))) => true,
+            _ => false
+        };
+        if !again {
+            {
+                let (own, target) = game.fields_of(turn);
+                let ended = match turn {
+                    Turn::PLAYER => first.end_turn(own, &opponent_view(target)),
+                    Turn::AI => second.end_turn(own, &opponent_view(target))
+                };
+                try!(ended.map_err(|e| (turn, e)));
+            }
+            game.turn = other_turn(turn);
+        }
+    }
+}
+
+// Both players hear the outcome, the first error is returned
+pub fn finish_game(first: &mut Player, second: &mut Player, winner: Turn) -> Result<(), (Turn, Failure)> {
+    let one = first.game_over(winner == Turn::PLAYER).map_err(|e| (Turn::PLAYER, e));
+    let two = second.game_over(winner == Turn::AI).map_err(|e| (Turn::AI, e));
+    one.and(two)
+}
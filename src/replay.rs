@@ -0,0 +1,192 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use field::*;
+use game::*;
+use save::*;
+
+pub static REPLAY_VERSION: u32 = 1;
+
+static REPLAY_HEADER: &'static str = "battleship-replay";
+
+// Fleets as they were placed and every shot of the game in order
+pub struct Replay {
+    pub userfield: Field,
+    pub enemyfield: Field,
+    pub shots: Vec<ShotRecord>
+}
+
+// Shots never clear ship ids, so the placement can be recovered from a played field
+pub fn initial_field(field: &Field) -> Field {
+    let mut initial = field.clone();
+    for i in 0..field.height {
+        for j in 0..field.width {
+            match initial.cell_mut(Pos { y : i as i32, x : j as i32 }) {
+                Some(cell) => {
+                    cell.Type = match cell.ship {
+                        Some(_) => CellType::SHIP,
+                        None => CellType::EMPTY
+                    };
+                }
+                None => {}
+            }
+        }
+    }
+    for ship in initial.ships.iter_mut() {
+        ship.hits = 0;
+    }
+    return initial;
+}
+
+pub fn game_replay(game: &Game) -> Replay {
+    Replay {
+        userfield : initial_field(&game.userfield),
+        enemyfield : initial_field(&game.enemyfield),
+        shots : game.history.clone()
+    }
+}
+
+// Only the other side knows where its ships were until it reveals them, so a
+// network game is replayed with the revealed fleet, see `reveal_and_verify`
+pub fn revealed_replay(game: &Game, enemyfield: &Field) -> Replay {
+    Replay {
+        userfield : initial_field(&game.userfield),
+        enemyfield : initial_field(enemyfield),
+        shots : game.history.clone()
+    }
+}
+
+// Both fields after the first `step` shots
+pub fn replay_step(replay: &Replay, step: usize) -> (Field, Field) {
+    let mut userfield = replay.userfield.clone();
+    let mut enemyfield = replay.enemyfield.clone();
+    for shot in replay.shots.iter().take(step) {
+        match shot.turn {
+            Turn::PLAYER => { tryToShot(&mut enemyfield, shot.coord.pos()); }
+            Turn::AI => { tryToShot(&mut userfield, shot.coord.pos()); }
+        }
+    }
+    return (userfield, enemyfield);
+}
+
+// Fills in the outcome of every shot by firing it at the placement again.
+// Outcomes read from a file must agree with it.
+pub fn resolve_shots(userfield: &Field, enemyfield: &Field, shots: &mut Vec<ShotRecord>, outcomes: &Vec<Option<String>>) -> Result<(), String> {
+    let mut userfield = userfield.clone();
+    let mut enemyfield = enemyfield.clone();
+    for (i, shot) in shots.iter_mut().enumerate() {
+        let result = match shot.turn {
+            Turn::PLAYER => tryToShot(&mut enemyfield, shot.coord.pos()),
+            Turn::AI => tryToShot(&mut userfield, shot.coord.pos())
+        };
+        match result {
+            ShotResult::ALREADY_SHOT | ShotResult::OUT_OF_BOUNDS => {
+                return Err(format!("Shot {} at {},{} is illegal", i + 1, shot.coord.y, shot.coord.x));
+            }
+            _ => {}
+        }
+        match outcomes.get(i) {
+            Some(&Some(ref outcome)) if outcome != outcome_name(&result) => {
+                return Err(format!("Shot {} was a {}, not a {}", i + 1, outcome_name(&result), outcome));
+            }
+            _ => {}
+        }
+        shot.result = result;
+    }
+    return Ok(());
+}
+
+pub fn save_replay(replay: &Replay) -> String {
+    let mut out = format!("{} {}\n", REPLAY_HEADER, REPLAY_VERSION);
+    out.push_str(&format!("adjacency {}\n", adjacency_name(replay.userfield.adjacency)));
+    out.push_str(&format!("size {} {}\n", replay.userfield.width, replay.userfield.height));
+    write_field(&mut out, "user", &replay.userfield);
+    write_field(&mut out, "enemy", &replay.enemyfield);
+    for shot in replay.shots.iter() {
+        write_shot(&mut out, shot);
+    }
+    out.push_str("end\n");
+    return out;
+}
+
+pub fn load_replay(text: &str) -> Result<Replay, String> {
+    let mut lines = text.lines();
+    let version = match lines.next() {
+        Some(header) => {
+            let mut tokens = header.split_whitespace();
+            if tokens.next() != Some(REPLAY_HEADER) {
+                return Err("Not a battleship replay".to_string());
+            }
+            try!(parse_number::<u32>(tokens.next(), header))
+        }
+        None => { return Err("Empty replay".to_string()); }
+    };
+    if version == 0 || version > REPLAY_VERSION {
+        return Err(format!("Unsupported replay version {}", version));
+    }
+
+    let mut adjacency = Adjacency::NO_TOUCHING;
+    let mut size : Option<(usize, usize)> = None;
+    let mut userfield : Option<Field> = None;
+    let mut enemyfield : Option<Field> = None;
+    let mut shots : Vec<ShotRecord> = vec![];
+    let mut outcomes : Vec<Option<String>> = vec![];
+
+    while let Some(line) = lines.next() {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("adjacency") => {
+                adjacency = try!(parse_adjacency(tokens.next(), line));
+            }
+            Some("size") => {
+                size = Some(try!(parse_size(&mut tokens, line)));
+            }
+            Some("field") => {
+                let (width, height) = match size {
+                    Some(size) => size,
+                    None => { return Err("Field comes before size".to_string()); }
+                };
+                let field = try!(read_field(&mut lines, width, height, adjacency));
+                match tokens.next() {
+                    Some("user") => { userfield = Some(field); }
+                    Some("enemy") => { enemyfield = Some(field); }
+                    _ => { return Err(format!("Bad field in '{}'", line)); }
+                }
+            }
+            Some("shot") => {
+                let (shot, outcome) = try!(parse_shot(line));
+                shots.push(shot);
+                outcomes.push(outcome);
+            }
+            Some("end") => { break; }
+            Some(_) => { return Err(format!("Unknown line '{}'", line)); }
+            None => {}
+        }
+    }
+
+    let userfield = match userfield {
+        Some(field) => initial_field(&field),
+        None => { return Err("Missing user field".to_string()); }
+    };
+    let enemyfield = match enemyfield {
+        Some(field) => initial_field(&field),
+        None => { return Err("Missing enemy field".to_string()); }
+    };
+    try!(resolve_shots(&userfield, &enemyfield, &mut shots, &outcomes));
+    Ok(Replay { userfield : userfield, enemyfield : enemyfield, shots : shots })
+}
+
+pub fn save_replay_to_file(replay: &Replay, path: &Path) -> io::Result<()> {
+    let mut file = try!(File::create(path));
+    file.write_all(save_replay(replay).as_bytes())
+}
+
+pub fn load_replay_from_file(path: &Path) -> Result<Replay, String> {
+    let mut text = String::new();
+    match File::open(path).and_then(|mut file| file.read_to_string(&mut text)) {
+        Ok(_) => load_replay(&text),
+        Err(e) => Err(e.to_string())
+    }
+}
@@ -0,0 +1,44 @@
+use rand;
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+// The only source of randomness of a game, so its seed reproduces the game.
+// Draws are counted to get back to the same point after loading a save.
+#[derive(Clone)]
+pub struct GameRng {
+    pub seed: u64,
+    pub draws: u64,
+    rng: XorShiftRng
+}
+
+pub fn seeded_rng(seed: u64) -> GameRng {
+    // The constant words keep the xorshift state from being all zeros
+    let state = [seed as u32, (seed >> 32) as u32, 0x9e3779b9, 0x243f6a88];
+    GameRng {
+        seed : seed,
+        draws : 0,
+        rng : SeedableRng::from_seed(state)
+    }
+}
+
+// Placing both fleets gives up long before, so a save with more is broken.
+// Restoring replays every draw.
+pub static MAX_DRAWS: u64 = 100000000;
+
+pub fn restored_rng(seed: u64, draws: u64) -> GameRng {
+    let mut rng = seeded_rng(seed);
+    for _ in 0..draws {
+        rng.next_u32();
+    }
+    return rng;
+}
+
+pub fn random_seed() -> u64 {
+    rand::thread_rng().gen()
+}
+
+impl Rng for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        self.draws += 1;
+        self.rng.next_u32()
+    }
+}
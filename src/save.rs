@@ -0,0 +1,557 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use field::*;
+use fleet::*;
+use game::*;
+use ai::*;
+use replay::*;
+use rng::*;
+
+// Bump it when the format changes and keep loading the older versions
+pub static SAVE_VERSION: u32 = 5;
+
+static SAVE_HEADER: &'static str = "battleship-save";
+
+fn cell_token(cell: &Cell) -> String {
+    let id = match cell.ship {
+        Some(id) => id.to_string(),
+        None => String::new()
+    };
+    match cell.Type {
+        CellType::EMPTY => ".".to_string(),
+        CellType::SHOT => "o".to_string(),
+        CellType::SHIP | CellType::COLLISION_SHIP if cell.ship.is_none() => "S".to_string(),
+        CellType::SHIP => format!("S{}", id),
+        CellType::COLLISION_SHIP => format!("X{}", id)
+    }
+}
+
+fn parse_cell(token: &str) -> Result<Cell, String> {
+    let mut chars = token.chars();
+    let kind = chars.next();
+    let id = chars.as_str();
+    let ship = if id.len() == 0 {
+        None
+    } else {
+        match id.parse::<usize>() {
+            Ok(id) => Some(id),
+            Err(_) => { return Err(format!("Bad cell '{}'", token)); }
+        }
+    };
+    let Type = match kind {
+        Some('.') => CellType::EMPTY,
+        Some('o') => CellType::SHOT,
+        Some('S') => CellType::SHIP,
+        Some('X') => CellType::COLLISION_SHIP,
+        // Hidden ships of older versions, the enemy fleet isn't masked anymore
+        Some('H') => CellType::SHIP,
+        _ => { return Err(format!("Bad cell '{}'", token)); }
+    };
+    Ok(Cell { Type : Type, ship : ship })
+}
+
+pub fn write_field(out: &mut String, name: &str, field: &Field) {
+    out.push_str(&format!("field {}\n", name));
+    for i in 0..field.height {
+        let row : Vec<String> = (0..field.width).filter_map(|j| field.cell(Pos { y : i as i32, x : j as i32 })).map(cell_token).collect();
+        out.push_str(&row.join(" "));
+        out.push_str("\n");
+    }
+}
+
+pub fn adjacency_name(adjacency: Adjacency) -> &'static str {
+    match adjacency {
+        Adjacency::NO_TOUCHING => "no-touching",
+        Adjacency::CORNERS_ALLOWED => "corners",
+        Adjacency::TOUCHING_ALLOWED => "touching"
+    }
+}
+
+pub fn parse_adjacency(token: Option<&str>, line: &str) -> Result<Adjacency, String> {
+    match token {
+        Some("no-touching") => Ok(Adjacency::NO_TOUCHING),
+        Some("corners") => Ok(Adjacency::CORNERS_ALLOWED),
+        Some("touching") => Ok(Adjacency::TOUCHING_ALLOWED),
+        _ => Err(format!("Bad adjacency in '{}'", line))
+    }
+}
+
+pub fn mode_name(mode: GameMode) -> String {
+    match mode {
+        GameMode::CLASSIC => "classic".to_string(),
+        GameMode::SALVO => "salvo".to_string(),
+        GameMode::FIXED_SALVO(shots) => format!("salvo {}", shots)
+    }
+}
+
+// Mode is the rest of the line: classic, salvo or salvo with a number of shots
+pub fn parse_mode<'a, I: Iterator<Item=&'a str>>(tokens: &mut I, line: &str) -> Result<GameMode, String> {
+    match tokens.next() {
+        Some("classic") => Ok(GameMode::CLASSIC),
+        Some("salvo") => match tokens.next() {
+            // Without shots a turn would never end
+            Some(shots) => match try!(parse_number(Some(shots), line)) {
+                0 => Err(format!("Salvo without shots in '{}'", line)),
+                shots => Ok(GameMode::FIXED_SALVO(shots))
+            },
+            None => Ok(GameMode::SALVO)
+        },
+        _ => Err(format!("Bad mode in '{}'", line))
+    }
+}
+
+pub fn outcome_name(result: &ShotResult) -> &'static str {
+    match *result {
+        ShotResult::MISS => "miss",
+        ShotResult::HIT => "hit",
+        ShotResult::SUNK(_) => "sunk",
+        ShotResult::ALREADY_SHOT => "already-shot",
+        ShotResult::OUT_OF_BOUNDS => "out-of-bounds"
+    }
+}
+
+pub fn write_shot(out: &mut String, shot: &ShotRecord) {
+    let turn = if shot.turn == Turn::PLAYER { "player" } else { "ai" };
+    out.push_str(&format!("shot {} {} {} {}\n", turn, shot.coord.y, shot.coord.x, outcome_name(&shot.result)));
+}
+
+// What the computer opponent has learned is saved too, it comes from its player
+pub fn save_game(game: &Game, ai: Option<&AiState>) -> String {
+    let mut out = format!("{} {}\n", SAVE_HEADER, SAVE_VERSION);
+    out.push_str(&format!("mode {}\n", mode_name(game.mode)));
+    match game.opponent {
+        Opponent::COMPUTER => out.push_str("opponent computer\n"),
+        Opponent::HUMAN => out.push_str("opponent human\n"),
+        Opponent::REMOTE => out.push_str("opponent remote\n"),
+        Opponent::BOT => out.push_str("opponent bot\n")
+    }
+    match ai.map(|ai| ai.difficulty) {
+        Some(Difficulty::EASY) => out.push_str("difficulty easy\n"),
+        Some(Difficulty::HARD) => out.push_str("difficulty hard\n"),
+        None => {}
+    }
+    out.push_str(&format!("adjacency {}\n", adjacency_name(game.userfield.adjacency)));
+    out.push_str(&format!("size {} {}\n", game.userfield.width, game.userfield.height));
+    match game.turn {
+        Turn::PLAYER => out.push_str("turn player\n"),
+        Turn::AI => out.push_str("turn ai\n")
+    }
+    out.push_str(&format!("fleet {}\n", game.fleet.name));
+    for ship in game.fleet.ships.iter() {
+        out.push_str(&format!("ship {}\n", ship_line(ship)));
+    }
+    out.push_str(&format!("seed {} {}\n", game.rng.seed, game.rng.draws));
+    for shot in game.history.iter() {
+        write_shot(&mut out, shot);
+    }
+    write_field(&mut out, "user", &game.userfield);
+    write_field(&mut out, "enemy", &game.enemyfield);
+
+    match ai {
+        Some(ai) => {
+            let hits : Vec<String> = ai.hits.iter().map(|hit| format!("{},{}", hit.y, hit.x)).collect();
+            out.push_str(&format!("ai-hits {}\n", hits.join(" ")));
+            let remaining : Vec<String> = ai.remaining.iter().map(|shape| shape_string(shape)).collect();
+            out.push_str(&format!("ai-remaining {}\n", remaining.join(" ")));
+            out.push_str("ai-blocked\n");
+            for row in ai.blocked.iter() {
+                let row : Vec<&str> = row.iter().map(|&blocked| if blocked { "1" } else { "0" }).collect();
+                out.push_str(&row.join(" "));
+                out.push_str("\n");
+            }
+        }
+        None => {}
+    }
+    out.push_str("end\n");
+    return out;
+}
+
+pub fn parse_number<T: ::std::str::FromStr>(token: Option<&str>, line: &str) -> Result<T, String> {
+    match token {
+        Some(token) => token.parse::<T>().map_err(|_| format!("Bad number in '{}'", line)),
+        None => Err(format!("Missing value in '{}'", line))
+    }
+}
+
+// Outcome is optional, older saves don't have it
+pub fn parse_shot(line: &str) -> Result<(ShotRecord, Option<String>), String> {
+    let mut tokens = line.split_whitespace().skip(1);
+    let turn = match tokens.next() {
+        Some("player") => Turn::PLAYER,
+        Some("ai") => Turn::AI,
+        _ => { return Err(format!("Bad shot in '{}'", line)); }
+    };
+    let y = try!(parse_number(tokens.next(), line));
+    let x = try!(parse_number(tokens.next(), line));
+    let outcome = tokens.next().map(|outcome| outcome.to_string());
+    // The real outcome is worked out again from the placement
+    Ok((ShotRecord { turn : turn, coord : Coord { y : y, x : x }, result : ShotResult::MISS }, outcome))
+}
+
+fn read_rows<'a, I: Iterator<Item=&'a str>>(lines: &mut I, height: usize, width: usize) -> Result<Vec<Vec<&'a str>>, String> {
+    let mut rows : Vec<Vec<&str>> = vec![];
+    for _ in 0..height {
+        let row : Vec<&str> = match lines.next() {
+            Some(line) => line.split_whitespace().collect(),
+            None => { return Err("Unexpected end of save".to_string()); }
+        };
+        if row.len() != width {
+            return Err(format!("Row has {} cells instead of {}", row.len(), width));
+        }
+        rows.push(row);
+    }
+    return Ok(rows);
+}
+
+// Field size of a save or a replay, only the sizes the game supports
+pub fn parse_size<'a, I: Iterator<Item=&'a str>>(tokens: &mut I, line: &str) -> Result<(usize, usize), String> {
+    let width = try!(parse_number(tokens.next(), line));
+    let height = try!(parse_number(tokens.next(), line));
+    if width < MIN_FIELD_SIZE || width > MAX_FIELD_SIZE || height < MIN_FIELD_SIZE || height > MAX_FIELD_SIZE {
+        return Err(format!("Unsupported field size {}x{}", width, height));
+    }
+    return Ok((width, height));
+}
+
+pub fn read_field<'a, I: Iterator<Item=&'a str>>(lines: &mut I, width: usize, height: usize, adjacency: Adjacency) -> Result<Field, String> {
+    let mut field = empty_field(width, height, adjacency);
+    let rows = try!(read_rows(lines, height, width));
+    for i in 0..height {
+        for j in 0..width {
+            let cell = try!(parse_cell(rows[i][j]));
+            // Ids are indexes of the ship registry, there can't be more ships than cells
+            match cell.ship {
+                Some(id) if id >= width * height => { return Err(format!("Bad ship id {}", id)); }
+                _ => {}
+            }
+            match field.cell_mut(Pos { y : i as i32, x : j as i32 }) {
+                Some(place) => { *place = cell; }
+                None => {}
+            }
+        }
+    }
+    register_ships(&mut field);
+    return Ok(field);
+}
+
+// The computer opponent's state comes with the game, hot seat games have none
+pub fn load_game(text: &str) -> Result<(Game, Option<AiState>), String> {
+    let mut lines = text.lines();
+    let version = match lines.next() {
+        Some(header) => {
+            let mut tokens = header.split_whitespace();
+            if tokens.next() != Some(SAVE_HEADER) {
+                return Err("Not a battleship save".to_string());
+            }
+            try!(parse_number::<u32>(tokens.next(), header))
+        }
+        None => { return Err("Empty save".to_string()); }
+    };
+    if version == 0 || version > SAVE_VERSION {
+        return Err(format!("Unsupported save version {}", version));
+    }
+
+    let mut mode = GameMode::CLASSIC;
+    // Saves before version 3 are all against the computer
+    let mut opponent = Opponent::COMPUTER;
+    let mut difficulty = Difficulty::EASY;
+    let mut adjacency = Adjacency::NO_TOUCHING;
+    let mut size : Option<(usize, usize)> = None;
+    let mut turn = Turn::PLAYER;
+    let mut fleet = Fleet { name : String::new(), ships : vec![] };
+    // Version 1 saves have no seed, they go on with a new one
+    let mut rng = seeded_rng(random_seed());
+    let mut history : Vec<ShotRecord> = vec![];
+    let mut outcomes : Vec<Option<String>> = vec![];
+    let mut userfield : Option<Field> = None;
+    let mut enemyfield : Option<Field> = None;
+    let mut hits : Vec<Coord> = vec![];
+    let mut remaining : Vec<Vec<Coord>> = vec![];
+    let mut blocked : Option<Vec<Vec<bool>>> = None;
+
+    while let Some(line) = lines.next() {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("mode") => {
+                mode = try!(parse_mode(&mut tokens, line));
+            }
+            Some("opponent") => {
+                opponent = match tokens.next() {
+                    Some("computer") => Opponent::COMPUTER,
+                    Some("human") => Opponent::HUMAN,
+                    Some("remote") => { return Err("Network games can't be resumed".to_string()); }
+                    Some("bot") => { return Err("Bot games can't be resumed".to_string()); }
+                    _ => { return Err(format!("Bad opponent in '{}'", line)); }
+                };
+            }
+            Some("difficulty") => {
+                difficulty = match tokens.next() {
+                    Some("easy") => Difficulty::EASY,
+                    Some("hard") => Difficulty::HARD,
+                    _ => { return Err(format!("Bad difficulty in '{}'", line)); }
+                };
+            }
+            Some("adjacency") => {
+                adjacency = try!(parse_adjacency(tokens.next(), line));
+            }
+            Some("size") => {
+                size = Some(try!(parse_size(&mut tokens, line)));
+            }
+            Some("turn") => {
+                turn = match tokens.next() {
+                    Some("player") => Turn::PLAYER,
+                    Some("ai") => Turn::AI,
+                    _ => { return Err(format!("Bad turn in '{}'", line)); }
+                };
+            }
+            Some("fleet") => {
+                fleet.name = tokens.collect::<Vec<&str>>().join(" ");
+            }
+            Some("ship") => {
+                fleet.ships.push(try!(parse_ship_line(&mut tokens, line)));
+            }
+            Some("seed") => {
+                let seed = try!(parse_number(tokens.next(), line));
+                let draws = try!(parse_number(tokens.next(), line));
+                if draws > MAX_DRAWS {
+                    return Err(format!("Too many draws in '{}'", line));
+                }
+                rng = restored_rng(seed, draws);
+            }
+            // Counters of versions before 4, the history and the fields tell the same
+            Some("stats") => {}
+            Some("shot") => {
+                let (shot, outcome) = try!(parse_shot(line));
+                history.push(shot);
+                outcomes.push(outcome);
+            }
+            Some("field") => {
+                let (width, height) = match size {
+                    Some(size) => size,
+                    None => { return Err("Field comes before size".to_string()); }
+                };
+                let field = try!(read_field(&mut lines, width, height, adjacency));
+                match tokens.next() {
+                    Some("user") => { userfield = Some(field); }
+                    Some("enemy") => { enemyfield = Some(field); }
+                    _ => { return Err(format!("Bad field in '{}'", line)); }
+                }
+            }
+            Some("ai-hits") => {
+                for token in tokens {
+                    let mut parts = token.split(',');
+                    let y = try!(parse_number(parts.next(), line));
+                    let x = try!(parse_number(parts.next(), line));
+                    hits.push(Coord { y : y, x : x });
+                }
+            }
+            Some("ai-remaining") => {
+                for token in tokens {
+                    remaining.push(try!(parse_shape(Some(token), line)));
+                }
+            }
+            Some("ai-blocked") => {
+                let (width, height) = match size {
+                    Some(size) => size,
+                    None => { return Err("AI state comes before size".to_string()); }
+                };
+                let rows = try!(read_rows(&mut lines, height, width));
+                blocked = Some(rows.iter().map(|row| row.iter().map(|&cell| cell == "1").collect()).collect());
+            }
+            Some("end") => { break; }
+            Some(_) => { return Err(format!("Unknown line '{}'", line)); }
+            None => {}
+        }
+    }
+
+    let (width, height) = match size {
+        Some(size) => size,
+        None => { return Err("Missing size".to_string()); }
+    };
+    try!(check_fleet(&fleet, width, height));
+    let mut game = new_game(width, height, adjacency, &fleet, mode, opponent, 0);
+    game.rng = rng;
+    game.userfield = match userfield {
+        Some(field) => field,
+        None => { return Err("Missing user field".to_string()); }
+    };
+    game.enemyfield = match enemyfield {
+        Some(field) => field,
+        None => { return Err("Missing enemy field".to_string()); }
+    };
+    name_ships(&mut game.userfield, &fleet);
+    name_ships(&mut game.enemyfield, &fleet);
+    game.turn = turn;
+    try!(resolve_shots(&initial_field(&game.userfield), &initial_field(&game.enemyfield), &mut history, &outcomes));
+    game.history = history;
+    if opponent != Opponent::COMPUTER {
+        return Ok((game, None));
+    }
+    let mut ai = new_ai_state(difficulty, width, height, &fleet);
+    ai.hits = hits;
+    ai.remaining = remaining;
+    match blocked {
+        Some(blocked) => { ai.blocked = blocked; }
+        None => {}
+    }
+    return Ok((game, Some(ai)));
+}
+
+pub fn save_to_file(game: &Game, ai: Option<&AiState>, path: &Path) -> io::Result<()> {
+    let mut file = try!(File::create(path));
+    file.write_all(save_game(game, ai).as_bytes())
+}
+
+pub fn load_from_file(path: &Path) -> Result<(Game, Option<AiState>), String> {
+    let mut text = String::new();
+    match File::open(path).and_then(|mut file| file.read_to_string(&mut text)) {
+        Ok(_) => load_game(&text),
+        Err(e) => Err(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Game against the computer after a few shots of each side
+    fn played_game() -> (Game, AiState) {
+        let fleet = polyomino_fleet();
+        let mut game = new_game(10, 10, Adjacency::CORNERS_ALLOWED, &fleet, GameMode::FIXED_SALVO(3), Opponent::COMPUTER, 42);
+        let mut ai = new_ai_state(Difficulty::HARD, 10, 10, &fleet);
+        assert!(ai_place_ship(&mut game.userfield, &fleet, &mut game.rng));
+        assert!(ai_place_ship(&mut game.enemyfield, &fleet, &mut game.rng));
+        for i in 0..10 {
+            let coord = Coord { y : i, x : i };
+            let result = tryToShot(&mut game.enemyfield, coord.pos());
+            game.record_shot(Turn::PLAYER, coord, &result);
+            let target = ai_choose(&opponent_view(&game.userfield), &ai, &mut game.rng).unwrap();
+            let result = tryToShot(&mut game.userfield, target.pos());
+            ai_observe(&opponent_view(&game.userfield), &mut ai, target, &result);
+            game.record_shot(Turn::AI, target, &result);
+        }
+        return (game, ai);
+    }
+
+    #[test]
+    fn saved_game_loads_the_same() {
+        let (game, ai) = played_game();
+        let text = save_game(&game, Some(&ai));
+        let (loaded, loaded_ai) = load_game(&text).unwrap();
+        assert_eq!(save_game(&loaded, loaded_ai.as_ref()), text);
+        assert_eq!(loaded.shot_stats(Turn::PLAYER), game.shot_stats(Turn::PLAYER));
+        assert_eq!(layout_string(&loaded.enemyfield), layout_string(&game.enemyfield));
+        let names : Vec<&str> = loaded.enemyfield.ships.iter().map(|ship| ship.name.as_str()).collect();
+        let expected : Vec<&str> = game.enemyfield.ships.iter().map(|ship| ship.name.as_str()).collect();
+        assert_eq!(names, expected);
+    }
+
+    // Same game on a 6x6 field written by older versions, shots are added per version
+    fn old_save(version: u32, extra: &str, shots: &str) -> String {
+        format!("battleship-save {}\n\
+                 mode classic\n\
+                 {}\
+                 difficulty hard\n\
+                 adjacency no-touching\n\
+                 size 6 6\n\
+                 turn player\n\
+                 fleet Tiny\n\
+                 ship 2 1 Destroyer\n\
+                 ship 1 1 Boat\n\
+                 stats 2 1 0 0\n\
+                 {}\
+                 field user\n\
+                 S0 . . . . .\n\
+                 S0 . . . . .\n\
+                 . . . . . .\n\
+                 . . . S1 . .\n\
+                 . . . . . .\n\
+                 o . . . . .\n\
+                 field enemy\n\
+                 X0 S0 . . . .\n\
+                 . . . . . .\n\
+                 . . . . . .\n\
+                 . . . . . .\n\
+                 . . . . S1 .\n\
+                 . . . . . o\n\
+                 ai-hits\n\
+                 ai-remaining 2 1\n\
+                 ai-blocked\n\
+                 0 0 0 0 0 0\n\
+                 0 0 0 0 0 0\n\
+                 0 0 0 0 0 0\n\
+                 0 0 0 0 0 0\n\
+                 0 0 0 0 0 0\n\
+                 0 0 0 0 0 0\n\
+                 end\n", version, extra, shots)
+    }
+
+    fn check_old_game(game: &Game) {
+        let outcomes : Vec<&str> = game.history.iter().map(|shot| outcome_name(&shot.result)).collect();
+        assert_eq!(outcomes, vec!["hit", "miss", "miss"]);
+        assert_eq!(game.shot_stats(Turn::PLAYER), (2, 1));
+        assert_eq!(game.shot_stats(Turn::AI), (1, 0));
+        assert_eq!(game.enemyfield.ships.len(), 2);
+        assert_eq!(game.enemyfield.ships[0].name, "Destroyer");
+        assert_eq!(game.enemyfield.ships[0].hits, 1);
+        assert_eq!(game.userfield.ships[1].name, "Boat");
+        assert!(game.fleet.ships[0].shape == straight_shape(2));
+        assert!(game.turn == Turn::PLAYER);
+        assert!(!game.is_over());
+        assert_eq!(mode_name(game.mode), "classic");
+    }
+
+    #[test]
+    fn version_1_save_loads() {
+        // The enemy fleet was hidden then
+        let text = old_save(1, "", "shot player 0 0\nshot player 5 5\nshot ai 5 0\n").replace("X0 S0", "X0 H0");
+        let (game, ai) = load_game(&text).unwrap();
+        check_old_game(&game);
+        assert_eq!(cell_token(game.enemyfield.cell(Pos { y : 0, x : 1 }).unwrap()), "S0");
+        assert!(game.opponent == Opponent::COMPUTER);
+        assert!(ai.unwrap().remaining == vec![straight_shape(2), straight_shape(1)]);
+    }
+
+    #[test]
+    fn version_3_save_loads() {
+        let text = old_save(3, "opponent human\nseed 7 12\n", "shot player 0 0 hit\nshot player 5 5 miss\nshot ai 5 0 miss\n");
+        let (game, ai) = load_game(&text).unwrap();
+        check_old_game(&game);
+        assert!(game.opponent == Opponent::HUMAN);
+        assert!(ai.is_none());
+        assert_eq!((game.rng.seed, game.rng.draws), (7, 12));
+    }
+
+    #[test]
+    fn wrong_outcomes_are_rejected() {
+        assert!(load_game(&old_save(1, "", "shot player 0 0 miss\n")).is_err());
+        assert!(load_game(&old_save(1, "", "shot player 0 0 hit\nshot player 0 1 hit\n")).is_err());
+    }
+
+    #[test]
+    fn broken_saves_are_rejected() {
+        let text = old_save(1, "", "");
+        assert!(load_game(&old_save(SAVE_VERSION + 1, "", "")).is_err());
+        assert!(load_game(&text.replace("mode classic", "mode salvo 0")).is_err());
+        assert!(load_game(&text.replace("size 6 6", "size 6 99999")).is_err());
+        assert!(load_game(&text.replace("X0 S0", "X0 S99999999")).is_err());
+        assert!(load_game(&text.replace("X0 S0", "X0 é")).is_err());
+        assert!(load_game(&text.replace("ship 1 1 Boat", "ship 1 99999999 Boat")).is_err());
+    }
+
+    #[test]
+    fn sunk_ships_come_from_the_fields() {
+        let text = old_save(3, "", "shot player 0 0 hit\n").replace("stats 2 1 0 0", "stats 1 1 2 2");
+        let (game, _) = load_game(&text).unwrap();
+        assert!(!game.is_over());
+        assert_eq!(sunk_ships(&game.enemyfield), 0);
+    }
+
+    #[test]
+    fn endless_seed_is_rejected() {
+        assert!(load_game(&old_save(2, "seed 7 18446744073709551615\n", "")).is_err());
+    }
+}
@@ -0,0 +1,254 @@
+// Round robin between the built-in AIs and bots (see `headless`), without
+// any terminal UI. Every pairing plays the same seeds, sides swap every game.
+
+extern crate battleship;
+
+use std::env;
+use std::fs::File;
+use std::io::Write;
+
+use battleship::field::*;
+use battleship::fleet::*;
+use battleship::game::*;
+use battleship::ai::*;
+use battleship::net::*;
+use battleship::bot::*;
+use battleship::headless::*;
+use battleship::player::*;
+
+enum Entrant {
+    BUILTIN(Difficulty),
+    COMMAND(String),
+}
+
+struct Score {
+    name: String,
+    games: u32,
+    wins: u32,
+    forfeits: u32,
+    // Shots of every win that wasn't a forfeit
+    shots_to_win: Vec<u32>
+}
+
+struct Args {
+    games: u32,
+    seed: u64,
+    timeout_ms: u64,
+    bots: Vec<String>,
+    builtin: bool,
+    csv: Option<String>
+}
+
+fn parse_args() -> Result<Args, String> {
+    let args : Vec<String> = env::args().collect();
+    let mut parsed = Args {
+        games : 100,
+        seed : 1,
+        timeout_ms : 1000,
+        bots : vec![],
+        builtin : true,
+        csv : None
+    };
+    let mut i = 1;
+    while i < args.len() {
+        if args[i] == "--games" && i + 1 < args.len() {
+            match args[i+1].parse::<u32>() {
+                Ok(value) if value > 0 => { parsed.games = value; }
+                _ => { return Err(format!("Bad number of games '{}'", args[i+1])); }
+            }
+            i += 2;
+        }
+        else if args[i] == "--seed" && i + 1 < args.len() {
+            match args[i+1].parse::<u64>() {
+                Ok(value) => { parsed.seed = value; }
+                Err(_) => { return Err(format!("Bad seed '{}'", args[i+1])); }
+            }
+            i += 2;
+        }
+        else if args[i] == "--timeout" && i + 1 < args.len() {
+            match args[i+1].parse::<u64>() {
+                Ok(value) => { parsed.timeout_ms = value; }
+                Err(_) => { return Err(format!("Bad timeout '{}'", args[i+1])); }
+            }
+            i += 2;
+        }
+        else if args[i] == "--bot" && i + 1 < args.len() {
+            parsed.bots.push(args[i+1].clone());
+            i += 2;
+        }
+        else if args[i] == "--no-builtin" {
+            parsed.builtin = false;
+            i += 1;
+        }
+        else if args[i] == "--csv" && i + 1 < args.len() {
+            parsed.csv = Some(args[i+1].clone());
+            i += 2;
+        }
+        else {
+            return Err(format!("Unknown argument '{}'", args[i]));
+        }
+    }
+    return Ok(parsed);
+}
+
+fn entrant_name(entrant: &Entrant) -> String {
+    match *entrant {
+        Entrant::BUILTIN(Difficulty::EASY) => "AI (easy)".to_string(),
+        Entrant::BUILTIN(Difficulty::HARD) => "AI (hard)".to_string(),
+        Entrant::COMMAND(ref command) => command.clone()
+    }
+}
+
+// Bots are started again for every game, the protocol ends with OVER
+fn new_player(entrant: &Entrant, rules: &Rules, timeout_ms: u64) -> Result<Box<Player>, String> {
+    match *entrant {
+        Entrant::BUILTIN(difficulty) => Ok(Box::new(ai_player(difficulty, rules.width, rules.height, &rules.fleet))),
+        Entrant::COMMAND(ref command) => {
+            let bot = try!(spawn_bot(command, timeout_ms));
+            Ok(Box::new(bot))
+        }
+    }
+}
+
+// 95% Wilson interval of a win rate
+fn wilson_interval(wins: u32, games: u32) -> (f64, f64) {
+    if games == 0 {
+        return (0.0, 1.0);
+    }
+    let z = 1.96;
+    let n = games as f64;
+    let p = wins as f64 / n;
+    let center = (p + z * z / (2.0 * n)) / (1.0 + z * z / n);
+    let spread = z * (p * (1.0 - p) / n + z * z / (4.0 * n * n)).sqrt() / (1.0 + z * z / n);
+    return (center - spread, center + spread);
+}
+
+// Mean and its 95% interval, from the normal approximation
+fn mean_interval(values: &Vec<u32>) -> Option<(f64, f64, f64)> {
+    if values.len() == 0 {
+        return None;
+    }
+    let n = values.len() as f64;
+    let mean = values.iter().map(|&v| v as f64).sum::<f64>() / n;
+    if values.len() == 1 {
+        return Some((mean, mean, mean));
+    }
+    let variance = values.iter().map(|&v| (v as f64 - mean) * (v as f64 - mean)).sum::<f64>() / (n - 1.0);
+    let spread = 1.96 * (variance / n).sqrt();
+    return Some((mean, mean - spread, mean + spread));
+}
+
+fn csv_field(text: &str) -> String {
+    format!("\"{}\"", text.replace("\"", "\"\""))
+}
+
+fn write_csv(path: &str, scores: &Vec<Score>) -> Result<(), String> {
+    let mut out = String::new();
+    out.push_str("entrant,games,wins,forfeits,win_rate,win_rate_low,win_rate_high,avg_shots_to_win,shots_low,shots_high\n");
+    for score in scores.iter() {
+        let (low, high) = wilson_interval(score.wins, score.games);
+        let shots = match mean_interval(&score.shots_to_win) {
+            Some((mean, low, high)) => format!("{:.2},{:.2},{:.2}", mean, low, high),
+            None => ",,".to_string()
+        };
+        out.push_str(&format!("{},{},{},{},{:.4},{:.4},{:.4},{}\n",
+                              csv_field(&score.name), score.games, score.wins, score.forfeits,
+                              score.wins as f64 / score.games as f64, low, high, shots));
+    }
+    let mut file = try!(File::create(path).map_err(|e| format!("Can't write {}: {}", path, e)));
+    file.write_all(out.as_bytes()).map_err(|e| format!("Can't write {}: {}", path, e))
+}
+
+fn print_table(scores: &Vec<Score>) {
+    let width = scores.iter().map(|score| score.name.len()).max().unwrap_or(0).max(7);
+    println!("{:<w$}  {:>6}  {:>6}  {:>8}  {:>16}  {:>20}", "Entrant", "Games", "Wins", "Forfeits", "Win rate (95%)", "Shots to win (95%)", w = width);
+    for score in scores.iter() {
+        let (low, high) = wilson_interval(score.wins, score.games);
+        let rate = format!("{:.1}% {:.0}-{:.0}", 100.0 * score.wins as f64 / score.games as f64, 100.0 * low, 100.0 * high);
+        let shots = match mean_interval(&score.shots_to_win) {
+            Some((mean, low, high)) => format!("{:.1} {:.1}-{:.1}", mean, low, high),
+            None => "-".to_string()
+        };
+        println!("{:<w$}  {:>6}  {:>6}  {:>8}  {:>16}  {:>20}", score.name, score.games, score.wins, score.forfeits, rate, shots, w = width);
+    }
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            println!("{}\nUsage: battleship-tournament [--games <n>] [--seed <number>] [--timeout <ms>] \
+                      [--bot <command>]... [--no-builtin] [--csv <file>]", e);
+            return;
+        }
+    };
+
+    let mut entrants : Vec<Entrant> = vec![];
+    if args.builtin {
+        entrants.push(Entrant::BUILTIN(Difficulty::EASY));
+        entrants.push(Entrant::BUILTIN(Difficulty::HARD));
+    }
+    for command in args.bots.iter() {
+        entrants.push(Entrant::COMMAND(command.clone()));
+    }
+    if entrants.len() < 2 {
+        println!("A tournament needs at least two entrants");
+        return;
+    }
+
+    let rules = Rules {
+        width : 10,
+        height : 10,
+        adjacency : Adjacency::NO_TOUCHING,
+        mode : GameMode::CLASSIC,
+        fleet : builtin_fleets()[0].clone()
+    };
+    let mut scores : Vec<Score> = entrants.iter().map(|entrant| Score {
+        name : entrant_name(entrant),
+        games : 0,
+        wins : 0,
+        forfeits : 0,
+        shots_to_win : vec![]
+    }).collect();
+
+    for a in 0..entrants.len() {
+        for b in (a + 1)..entrants.len() {
+            for n in 0..args.games {
+                // Same seeds for every pairing, the first shot alternates
+                let (first, second) = if n % 2 == 0 { (a, b) } else { (b, a) };
+                let seed = args.seed.wrapping_add(n as u64);
+                let mut one = match new_player(&entrants[first], &rules, args.timeout_ms) {
+                    Ok(c) => c,
+                    Err(e) => { println!("{}", e); return; }
+                };
+                let mut two = match new_player(&entrants[second], &rules, args.timeout_ms) {
+                    Ok(c) => c,
+                    Err(e) => { println!("{}", e); return; }
+                };
+                let result = play_match(&mut *one, &mut *two, &rules, seed);
+                let (winner, loser) = match result.winner {
+                    Turn::PLAYER => (first, second),
+                    Turn::AI => (second, first)
+                };
+                scores[first].games += 1;
+                scores[second].games += 1;
+                scores[winner].wins += 1;
+                match result.forfeit {
+                    Some(_) => { scores[loser].forfeits += 1; }
+                    None => { scores[winner].shots_to_win.push(result.game.shot_stats(result.winner).0); }
+                }
+            }
+        }
+    }
+
+    print_table(&scores);
+    match args.csv {
+        Some(ref path) => {
+            match write_csv(path, &scores) {
+                Ok(()) => {}
+                Err(e) => { println!("{}", e); }
+            }
+        }
+        None => {}
+    }
+}